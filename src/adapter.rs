@@ -0,0 +1,217 @@
+//! Use an [`AsyncResolver`] where a [`Resolver`] is expected and vice versa
+//!
+//! Both adapters keep the inner resolver in an [`Arc`] because the lookups
+//! run on other threads than the caller's.
+
+use crate::rt::{spawn_blocking, Executor};
+use crate::{
+    AsyncResolver, DomainName, QueryType, Record, ResolveResult, Resolver, SendAsyncResolver,
+};
+use std::sync::Arc;
+
+/// A [`Resolver`] driving an [`AsyncResolver`] on its own executor thread
+///
+/// The calling thread blocks until the lookup completed. The lookups of
+/// concurrent callers share the executor, so `A` must not block it, and
+/// move to its thread, so `A` has to be a [`SendAsyncResolver`].
+pub struct BlockingResolver<A> {
+    inner: Arc<A>,
+    executor: Executor,
+}
+
+impl<A: SendAsyncResolver + 'static> BlockingResolver<A> {
+    pub fn new(inner: A) -> Self {
+        BlockingResolver {
+            inner: Arc::new(inner),
+            executor: Executor::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+impl<A: SendAsyncResolver + 'static> Resolver for BlockingResolver<A> {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        let inner = self.inner.clone();
+        let name = name.clone();
+        self.executor
+            .run(async move { SendAsyncResolver::resolve_specific(&*inner, &name, query).await })
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let inner = self.inner.clone();
+        let name = name.clone();
+        let queries: Vec<_> = queries.collect();
+        self.executor.run(async move {
+            SendAsyncResolver::resolve_many(&*inner, &name, queries.into_iter()).await
+        })
+    }
+
+    fn clear_cache(&self) -> Result<(), ()> {
+        let inner = self.inner.clone();
+        self.executor
+            .run(async move { SendAsyncResolver::clear_cache(&*inner).await })
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        let inner = self.inner.clone();
+        self.executor
+            .run(async move { SendAsyncResolver::reload_system_config(&*inner).await })
+    }
+}
+
+/// An [`AsyncResolver`] running a blocking [`Resolver`] on a thread pool
+///
+/// Threads are started when all of them are busy and end after being idle
+/// for a while.
+pub struct AsyncFromSync<R> {
+    inner: Arc<R>,
+}
+
+impl<R: Resolver + Send + Sync + 'static> AsyncFromSync<R> {
+    pub fn new(inner: R) -> Self {
+        AsyncFromSync {
+            inner: Arc::new(inner),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: Resolver + Send + Sync + 'static> AsyncResolver for AsyncFromSync<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        let inner = self.inner.clone();
+        let name = name.clone();
+        spawn_blocking(move || inner.resolve_specific(&name, query)).await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let inner = self.inner.clone();
+        let name = name.clone();
+        let queries: Vec<_> = queries.collect();
+        spawn_blocking(move || inner.resolve_many(&name, queries.into_iter())).await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        let inner = self.inner.clone();
+        spawn_blocking(move || inner.clear_cache()).await
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        let inner = self.inner.clone();
+        spawn_blocking(move || inner.reload_system_config()).await
+    }
+}
+
+impl<R: Resolver + Send + Sync + 'static> SendAsyncResolver for AsyncFromSync<R> {
+    crate::forward_async_resolver!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::timer::Delay;
+    use crate::{collect_many, HostsFile, HostsFileResolver, RecordData, ResolveError};
+    use std::net::IpAddr;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Answers A queries after a delay that needs the timer to wake it up
+    struct Sleepy;
+
+    impl AsyncResolver for Sleepy {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            Delay::new(Duration::from_millis(50)).await;
+            match query {
+                QueryType::A => Ok(Record::new(
+                    name.clone(),
+                    Duration::from_secs(60),
+                    RecordData::IpAddr(vec![IpAddr::from([192, 0, 2, 1])]),
+                )),
+                _ => Err(ResolveError::NoData {
+                    name: name.clone(),
+                    query,
+                    ttl: None,
+                }),
+            }
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            let mut results = Vec::new();
+            for query in queries {
+                results.push(AsyncResolver::resolve_specific(self, name, query).await);
+            }
+            collect_many(results.into_iter())
+        }
+    }
+
+    impl SendAsyncResolver for Sleepy {
+        crate::forward_async_resolver!();
+    }
+
+    #[test]
+    fn blocks_on_async_resolvers() {
+        let resolver = Arc::new(BlockingResolver::new(Sleepy));
+        let name: DomainName = "www.example.com".parse().unwrap();
+        let start = Instant::now();
+        let callers: Vec<_> = (0..4)
+            .map(|_| {
+                let resolver = resolver.clone();
+                let name = name.clone();
+                thread::spawn(move || resolver.resolve(&name))
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(
+                caller.join().unwrap().unwrap(),
+                IpAddr::from([192, 0, 2, 1])
+            );
+        }
+        // the lookups shared the executor instead of running one after another
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(resolver.resolve_all(&name).unwrap().len(), 1);
+        assert_eq!(resolver.clear_cache(), Err(()));
+    }
+
+    #[test]
+    fn offloads_blocking_resolvers() {
+        let hosts = HostsFile::parse("192.0.2.9 printer.example\n");
+        let resolver = AsyncFromSync::new(HostsFileResolver::new(hosts));
+        let name = "printer.example".parse().unwrap();
+        assert_eq!(
+            block_on(AsyncResolver::resolve(&resolver, &name)).unwrap(),
+            IpAddr::from([192, 0, 2, 9])
+        );
+        let reverse = block_on(AsyncResolver::reverse(
+            &resolver,
+            IpAddr::from([192, 0, 2, 9]),
+        ))
+        .unwrap();
+        assert_eq!(reverse.len(), 1);
+        assert_eq!(
+            block_on(AsyncResolver::reload_system_config(&resolver)),
+            Err(())
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! Destination address selection following RFC 6724
+//!
+//! The sort only needs the destination addresses and a way to find the
+//! source address the host would use for each of them. [`AddressSelection::sort`]
+//! asks the operating system by connecting a UDP socket, while
+//! [`AddressSelection::sort_with`] accepts any lookup so the rules can be
+//! exercised in memory.
+//!
+//! Rules 3 (deprecated sources), 4 (home addresses) and 7 (native transport)
+//! need interface state that is not portable and are skipped.
+
+use crate::{collect_addresses, DomainName, QueryType, ResolveResult, Resolver};
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+
+/// An IPv6 prefix, IPv4 addresses are matched as `::ffff:0:0/96`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    pub address: Ipv6Addr,
+    pub len: u8,
+}
+
+impl Prefix {
+    pub fn contains(&self, address: &Ipv6Addr) -> bool {
+        common_prefix_len(&self.address, address) >= u32::from(self.len)
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = PolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, len) = s.split_once('/').unwrap_or((s, "128"));
+        let address: Ipv6Addr = address.parse().map_err(|_| PolicyParseError(0))?;
+        let len: u8 = len.parse().map_err(|_| PolicyParseError(0))?;
+        if len > 128 {
+            return Err(PolicyParseError(0));
+        }
+        Ok(Prefix { address, len })
+    }
+}
+
+/// The precedence and label tables of RFC 6724 section 2.1
+#[derive(Debug, Clone)]
+pub struct PolicyTable {
+    pub precedence: Vec<(Prefix, u32)>,
+    pub label: Vec<(Prefix, u32)>,
+}
+
+impl Default for PolicyTable {
+    fn default() -> Self {
+        const DEFAULT: [(&str, u32, u32); 9] = [
+            ("::1/128", 50, 0),
+            ("::/0", 40, 1),
+            ("::ffff:0:0/96", 35, 4),
+            ("2002::/16", 30, 2),
+            ("2001::/32", 5, 5),
+            ("fc00::/7", 3, 13),
+            ("::/96", 1, 3),
+            ("fec0::/10", 1, 11),
+            ("3ffe::/16", 1, 12),
+        ];
+        let entries = DEFAULT.iter().map(|(prefix, precedence, label)| {
+            (prefix.parse::<Prefix>().unwrap(), *precedence, *label)
+        });
+        PolicyTable {
+            precedence: entries
+                .clone()
+                .map(|(p, precedence, _)| (p, precedence))
+                .collect(),
+            label: entries.map(|(p, _, label)| (p, label)).collect(),
+        }
+    }
+}
+
+impl PolicyTable {
+    /// Read the `precedence` and `label` lines of a glibc `gai.conf`
+    ///
+    /// Like glibc, a table that appears in the file replaces the default one
+    /// completely while a missing table keeps the RFC defaults.
+    pub fn from_gai_conf(content: &str) -> Result<Self, PolicyParseError> {
+        let mut precedence = Vec::new();
+        let mut label = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let table = match fields.next() {
+                Some("precedence") => &mut precedence,
+                Some("label") => &mut label,
+                // reload, scopev4 and empty lines
+                _ => continue,
+            };
+            let error = PolicyParseError(index + 1);
+            let prefix = fields.next().ok_or(error)?.parse().map_err(|_| error)?;
+            let value = fields.next().ok_or(error)?.parse().map_err(|_| error)?;
+            table.push((prefix, value));
+        }
+
+        let mut table = PolicyTable::default();
+        if !precedence.is_empty() {
+            table.precedence = precedence;
+        }
+        if !label.is_empty() {
+            table.label = label;
+        }
+        Ok(table)
+    }
+
+    pub fn precedence(&self, address: &IpAddr) -> u32 {
+        lookup(&self.precedence, address)
+    }
+
+    pub fn label(&self, address: &IpAddr) -> u32 {
+        lookup(&self.label, address)
+    }
+}
+
+fn lookup(table: &[(Prefix, u32)], address: &IpAddr) -> u32 {
+    let address = to_v6(address);
+    table
+        .iter()
+        .filter(|(prefix, _)| prefix.contains(&address))
+        .max_by_key(|(prefix, _)| prefix.len)
+        .map(|(_, value)| *value)
+        .unwrap_or_default()
+}
+
+/// A line of a policy file could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyParseError(pub usize);
+
+impl fmt::Display for PolicyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid policy in line {}", self.0)
+    }
+}
+
+impl std::error::Error for PolicyParseError {}
+
+/// Address scopes of RFC 4007, smaller values are more local
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    InterfaceLocal = 0x1,
+    LinkLocal = 0x2,
+    AdminLocal = 0x4,
+    SiteLocal = 0x5,
+    OrganizationLocal = 0x8,
+    Global = 0xe,
+}
+
+impl Scope {
+    /// The scope of `address` as defined in RFC 6724 section 3.1
+    pub fn of(address: &IpAddr) -> Self {
+        match address {
+            IpAddr::V4(v4) if v4.is_loopback() || v4.is_link_local() => Scope::LinkLocal,
+            IpAddr::V4(_) => Scope::Global,
+            IpAddr::V6(v6) if v6.is_multicast() => match v6.segments()[0] & 0xf {
+                0x1 => Scope::InterfaceLocal,
+                0x2 => Scope::LinkLocal,
+                0x4 => Scope::AdminLocal,
+                0x5 => Scope::SiteLocal,
+                0x8 => Scope::OrganizationLocal,
+                _ => Scope::Global,
+            },
+            IpAddr::V6(v6) if v6.is_loopback() || v6.segments()[0] & 0xffc0 == 0xfe80 => {
+                Scope::LinkLocal
+            }
+            IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfec0 => Scope::SiteLocal,
+            IpAddr::V6(_) => Scope::Global,
+        }
+    }
+}
+
+/// Sorts destination addresses by the rules of RFC 6724 section 6
+#[derive(Debug, Clone, Default)]
+pub struct AddressSelection {
+    pub policy: PolicyTable,
+}
+
+impl AddressSelection {
+    /// Sort `destinations` using the source addresses the system would pick
+    pub fn sort(&self, destinations: &mut [IpAddr]) {
+        self.sort_with(destinations, system_source_address)
+    }
+
+    /// Sort `destinations` with `source` returning the source address used
+    /// to reach a destination, or `None` if it is unreachable
+    pub fn sort_with<F>(&self, destinations: &mut [IpAddr], source: F)
+    where
+        F: Fn(&IpAddr) -> Option<IpAddr>,
+    {
+        let mut candidates: Vec<_> = destinations
+            .iter()
+            .map(|destination| (*destination, source(destination)))
+            .collect();
+        // stable, so rule 10 keeps the original order
+        candidates.sort_by(|a, b| self.compare(a, b));
+        for (slot, (destination, _)) in destinations.iter_mut().zip(candidates) {
+            *slot = destination;
+        }
+    }
+
+    /// Resolve AAAA and A of `name` and return the addresses in preference order
+    pub fn resolve<R: Resolver + ?Sized>(
+        &self,
+        resolver: &R,
+        name: &DomainName,
+    ) -> ResolveResult<Vec<IpAddr>> {
+        let queries = [QueryType::AAAA, QueryType::A];
+        let records = resolver.resolve_many(name, queries.into_iter())?;
+        let mut addresses: Vec<_> = collect_addresses(name, &queries, records)?
+            .into_iter()
+            .map(|entry| entry.value)
+            .collect();
+        self.sort(&mut addresses);
+        Ok(addresses)
+    }
+
+    /// `Less` means `a` is preferred
+    fn compare(
+        &self,
+        (da, sa): &(IpAddr, Option<IpAddr>),
+        (db, sb): &(IpAddr, Option<IpAddr>),
+    ) -> Ordering {
+        let (sa, sb) = match (sa, sb) {
+            (Some(sa), Some(sb)) => (sa, sb),
+            // Rule 1: Avoid unusable destinations
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => return Ordering::Equal,
+        };
+
+        // Rule 2: Prefer matching scope
+        let scope_a = Scope::of(da);
+        let scope_b = Scope::of(db);
+        let matching_scope_a = scope_a == Scope::of(sa);
+        let matching_scope_b = scope_b == Scope::of(sb);
+        if matching_scope_a != matching_scope_b {
+            return matching_scope_b.cmp(&matching_scope_a);
+        }
+
+        // Rule 5: Prefer matching label
+        let matching_label_a = self.policy.label(da) == self.policy.label(sa);
+        let matching_label_b = self.policy.label(db) == self.policy.label(sb);
+        if matching_label_a != matching_label_b {
+            return matching_label_b.cmp(&matching_label_a);
+        }
+
+        // Rule 6: Prefer higher precedence
+        let precedence = self.policy.precedence(db).cmp(&self.policy.precedence(da));
+        if precedence != Ordering::Equal {
+            return precedence;
+        }
+
+        // Rule 8: Prefer smaller scope
+        if scope_a != scope_b {
+            return scope_a.cmp(&scope_b);
+        }
+
+        // Rule 9: Use longest matching prefix, only within the first 64 bits
+        // of IPv6 as IPv4 prefixes are unrelated to the network topology
+        if let (IpAddr::V6(da), IpAddr::V6(sa), IpAddr::V6(db), IpAddr::V6(sb)) = (da, sa, db, sb) {
+            let prefix_a = common_prefix_len(da, sa).min(64);
+            let prefix_b = common_prefix_len(db, sb).min(64);
+            return prefix_b.cmp(&prefix_a);
+        }
+
+        // Rule 10: Otherwise, leave the order unchanged
+        Ordering::Equal
+    }
+}
+
+/// Ask the kernel which source it would use, without sending a packet
+pub fn system_source_address(destination: &IpAddr) -> Option<IpAddr> {
+    let bind: SocketAddr = match destination {
+        IpAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect((*destination, 9)).ok()?;
+    socket.local_addr().ok().map(|local| local.ip())
+}
+
+fn to_v6(address: &IpAddr) -> Ipv6Addr {
+    match address {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => *v6,
+    }
+}
+
+fn common_prefix_len(a: &Ipv6Addr, b: &Ipv6Addr) -> u32 {
+    (u128::from(*a) ^ u128::from(*b)).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn sort(
+        selection: &AddressSelection,
+        destinations: &[&str],
+        sources: &[(&str, &str)],
+    ) -> Vec<IpAddr> {
+        let sources: HashMap<IpAddr, IpAddr> = sources
+            .iter()
+            .map(|(d, s)| (d.parse().unwrap(), s.parse().unwrap()))
+            .collect();
+        let mut destinations: Vec<IpAddr> =
+            destinations.iter().map(|d| d.parse().unwrap()).collect();
+        selection.sort_with(&mut destinations, |d| sources.get(d).copied());
+        destinations
+    }
+
+    fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+        addresses.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    // The examples of RFC 6724 section 10.2
+    #[test]
+    fn rfc_examples() {
+        let selection = AddressSelection::default();
+
+        // Prefer matching scope
+        let sorted = sort(
+            &selection,
+            &["2001:db8:1::1", "fe80::1"],
+            &[("2001:db8:1::1", "fe80::2"), ("fe80::1", "fe80::2")],
+        );
+        assert_eq!(sorted, ips(&["fe80::1", "2001:db8:1::1"]));
+
+        // Prefer higher precedence
+        let sorted = sort(
+            &selection,
+            &["198.51.100.121", "2001:db8:1::1"],
+            &[
+                ("2001:db8:1::1", "2001:db8:1::2"),
+                ("198.51.100.121", "169.254.13.78"),
+            ],
+        );
+        assert_eq!(sorted, ips(&["2001:db8:1::1", "198.51.100.121"]));
+
+        // Prefer matching label, ULA to ULA
+        let sorted = sort(
+            &selection,
+            &["2001:db8:1::1", "fd00:1::1"],
+            &[("2001:db8:1::1", "fd00:2::2"), ("fd00:1::1", "fd00:2::2")],
+        );
+        assert_eq!(sorted, ips(&["fd00:1::1", "2001:db8:1::1"]));
+
+        // Avoid unusable destinations
+        let sorted = sort(
+            &selection,
+            &["2001:db8:1::1", "198.51.100.121"],
+            &[("198.51.100.121", "198.51.100.117")],
+        );
+        assert_eq!(sorted, ips(&["198.51.100.121", "2001:db8:1::1"]));
+
+        // Loopback beats everything
+        let sorted = sort(
+            &selection,
+            &["2001:db8:1::1", "::1"],
+            &[("2001:db8:1::1", "2001:db8:1::2"), ("::1", "::1")],
+        );
+        assert_eq!(sorted, ips(&["::1", "2001:db8:1::1"]));
+    }
+
+    #[test]
+    fn longest_matching_prefix() {
+        let selection = AddressSelection::default();
+        let sorted = sort(
+            &selection,
+            &["2001:db8:2::1", "2001:db8:1::1"],
+            &[
+                ("2001:db8:2::1", "2001:db8:1::2"),
+                ("2001:db8:1::1", "2001:db8:1::2"),
+            ],
+        );
+        assert_eq!(sorted, ips(&["2001:db8:1::1", "2001:db8:2::1"]));
+    }
+
+    #[test]
+    fn gai_conf_prefers_ipv4() {
+        let conf = "# prefer IPv4\n\
+                    precedence ::1/128 50\n\
+                    precedence ::/0 40\n\
+                    precedence 2002::/16 30\n\
+                    precedence ::/96 20\n\
+                    precedence ::ffff:0:0/96 100\n";
+        let selection = AddressSelection {
+            policy: PolicyTable::from_gai_conf(conf).unwrap(),
+        };
+        let sorted = sort(
+            &selection,
+            &["2001:db8:1::1", "198.51.100.121"],
+            &[
+                ("2001:db8:1::1", "2001:db8:1::2"),
+                ("198.51.100.121", "198.51.100.117"),
+            ],
+        );
+        assert_eq!(sorted, ips(&["198.51.100.121", "2001:db8:1::1"]));
+        assert_eq!(
+            PolicyTable::from_gai_conf("label ::/0").unwrap_err(),
+            PolicyParseError(1)
+        );
+    }
+}
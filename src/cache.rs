@@ -0,0 +1,529 @@
+//! A cache in front of any resolver
+//!
+//! Answers are kept for their TTL clamped to the configured bounds, missing
+//! names are remembered for the negative TTL of the zone's SOA (RFC 2308)
+//! and the least recently used entry is evicted once the cache is full.
+
+use crate::{
+    collect_many, lookup_each, AsyncResolver, DomainName, QueryType, Record, RecordSource,
+    ResolveResult, Resolver, SendAsyncResolver,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits of a [`CachingResolver`]
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Answers are kept at least this long, even with a smaller TTL
+    pub min_ttl: Duration,
+    /// Answers are dropped after this time, even with a larger TTL
+    ///
+    /// Wins over `min_ttl` if it is smaller.
+    pub max_ttl: Duration,
+    /// How long NXDOMAIN and NODATA answers are remembered at most, and
+    /// without a TTL from the SOA record at all
+    pub negative_ttl: Duration,
+    /// Number of entries before the least recently used one is evicted
+    pub capacity: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            min_ttl: Duration::ZERO,
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+            negative_ttl: Duration::from_secs(60),
+            capacity: 1024,
+        }
+    }
+}
+
+/// Wraps a resolver and answers repeated queries from memory
+pub struct CachingResolver<R> {
+    inner: R,
+    config: CacheConfig,
+    cache: Mutex<Lru>,
+}
+
+type Key = (DomainName, QueryType);
+
+struct Entry {
+    /// The answer or the negative answer
+    result: ResolveResult<Record>,
+    expires: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<Key, Entry>,
+    /// last use to key, the first entry is evicted first
+    order: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &Key, now: Instant) -> Option<ResolveResult<Record>> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires <= now {
+            let last_used = entry.last_used;
+            self.entries.remove(key);
+            self.order.remove(&last_used);
+            return None;
+        }
+
+        self.tick += 1;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.tick, key.clone());
+        entry.last_used = self.tick;
+
+        Some(match &entry.result {
+            Ok(record) => {
+                let mut record = record.clone();
+                record.ttl = entry.expires - now;
+                record.source = RecordSource::Cache;
+                Ok(record)
+            }
+            Err(e) => Err(e.clone()),
+        })
+    }
+
+    fn insert(
+        &mut self,
+        key: Key,
+        result: ResolveResult<Record>,
+        expires: Instant,
+        capacity: usize,
+    ) {
+        if capacity == 0 {
+            return;
+        }
+        if let Some(old) = self.entries.remove(&key) {
+            self.order.remove(&old.last_used);
+        }
+        while self.entries.len() >= capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                result,
+                expires,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+impl<R> CachingResolver<R> {
+    /// Cache `inner` with the default limits
+    pub fn new(inner: R) -> Self {
+        Self::with_config(inner, CacheConfig::default())
+    }
+
+    pub fn with_config(inner: R, config: CacheConfig) -> Self {
+        CachingResolver {
+            inner,
+            config,
+            cache: Mutex::new(Lru::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// Number of cached answers, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lookup(&self, name: &DomainName, query: QueryType) -> Option<ResolveResult<Record>> {
+        let key = (name.clone(), query);
+        self.cache.lock().unwrap().get(&key, Instant::now())
+    }
+
+    /// Remember `result` if it is an answer or a negative answer
+    fn store(&self, name: &DomainName, query: QueryType, result: &ResolveResult<Record>) {
+        let ttl = match result {
+            Ok(record) => record.ttl.max(self.config.min_ttl).min(self.config.max_ttl),
+            Err(e) if e.is_negative() => e.negative_ttl().map_or(self.config.negative_ttl, |ttl| {
+                ttl.min(self.config.negative_ttl)
+            }),
+            // transient errors are worth retrying
+            Err(_) => return,
+        };
+        if ttl.is_zero() {
+            return;
+        }
+        let key = (name.clone(), query);
+        self.cache.lock().unwrap().insert(
+            key,
+            result.clone(),
+            Instant::now() + ttl,
+            self.config.capacity,
+        );
+    }
+
+    fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// The cached answer, otherwise the one of `lookup`
+    ///
+    /// The lookup is only started on a miss, some resolvers begin to work
+    /// as soon as the future is created.
+    async fn cached<F: Future<Output = ResolveResult<Record>>>(
+        &self,
+        name: &DomainName,
+        query: QueryType,
+        lookup: impl FnOnce() -> F,
+    ) -> ResolveResult<Record> {
+        if let Some(result) = self.lookup(name, query) {
+            return result;
+        }
+        let result = lookup().await;
+        self.store(name, query, &result);
+        result
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        if let Some(result) = self.lookup(name, query) {
+            return result;
+        }
+        let result = self.inner.resolve_specific(name, query);
+        self.store(name, query, &result);
+        result
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        collect_many(queries.map(|query| self.resolve_specific(name, query)))
+    }
+
+    /// Drops all cached answers and clears the inner cache if it has one
+    fn clear_cache(&self) -> Result<(), ()> {
+        self.clear();
+        let _ = self.inner.clear_cache();
+        Ok(())
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        self.inner.reload_system_config()?;
+        self.clear();
+        Ok(())
+    }
+}
+
+impl<R: AsyncResolver> AsyncResolver for CachingResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.cached(name, query, || self.inner.resolve_specific(name, query))
+            .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        lookup_each(queries, |query| {
+            AsyncResolver::resolve_specific(self, name, query)
+        })
+        .await
+    }
+
+    /// Drops all cached answers and clears the inner cache if it has one
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.clear();
+        let _ = self.inner.clear_cache().await;
+        Ok(())
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        self.inner.reload_system_config().await?;
+        self.clear();
+        Ok(())
+    }
+}
+
+impl<R: SendAsyncResolver> SendAsyncResolver for CachingResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.cached(name, query, || {
+            SendAsyncResolver::resolve_specific(&self.inner, name, query)
+        })
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        lookup_each(queries, |query| {
+            SendAsyncResolver::resolve_specific(self, name, query)
+        })
+        .await
+    }
+
+    /// Drops all cached answers and clears the inner cache if it has one
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.clear();
+        let _ = SendAsyncResolver::clear_cache(&self.inner).await;
+        Ok(())
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        SendAsyncResolver::reload_system_config(&self.inner).await?;
+        self.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RecordData, ResolveError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+
+    /// Knows `example.com` with the given TTL and counts the lookups
+    struct Counting {
+        ttl: Duration,
+        lookups: AtomicUsize,
+    }
+
+    impl Counting {
+        fn new(ttl: Duration) -> Self {
+            Counting {
+                ttl,
+                lookups: AtomicUsize::new(0),
+            }
+        }
+
+        fn lookups(&self) -> usize {
+            self.lookups.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Resolver for Counting {
+        fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            match (name.as_str(), query) {
+                ("example.com", QueryType::A) => Ok(Record::new(
+                    name.clone(),
+                    self.ttl,
+                    RecordData::IpAddr(vec!["192.0.2.1".parse().unwrap()]),
+                )),
+                ("soa.example", _) => Err(ResolveError::NoData {
+                    name: name.clone(),
+                    query,
+                    ttl: Some(self.ttl),
+                }),
+                _ => Err(ResolveError::NxDomain {
+                    name: name.clone(),
+                    query,
+                    ttl: None,
+                }),
+            }
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| self.resolve_specific(name, query)))
+        }
+    }
+
+    /// Looks up as soon as the future is created, like a resolver that
+    /// spawns its lookups
+    struct Eager(Counting);
+
+    impl AsyncResolver for Eager {
+        fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> impl Future<Output = ResolveResult<Record>> {
+            std::future::ready(self.0.resolve_specific(name, query))
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> impl Future<Output = ResolveResult<Vec<Record>>> {
+            std::future::ready(self.0.resolve_many(name, queries))
+        }
+    }
+
+    fn name(name: &str) -> DomainName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn answers_from_cache_until_ttl_expires() {
+        let resolver = CachingResolver::new(Counting::new(Duration::from_millis(50)));
+        let first = resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        assert_eq!(first.source, RecordSource::Recursive);
+        let second = resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        assert_eq!(second.source, RecordSource::Cache);
+        assert!(second.ttl <= Duration::from_millis(50));
+        assert_eq!(resolver.inner().lookups(), 1);
+
+        sleep(Duration::from_millis(60));
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        assert_eq!(resolver.inner().lookups(), 2);
+    }
+
+    #[test]
+    fn hits_do_not_start_lookups() {
+        let resolver = CachingResolver::new(Eager(Counting::new(Duration::from_secs(60))));
+        for _ in 0..3 {
+            crate::rt::block_on(resolver.resolve_specific(&name("example.com"), QueryType::A))
+                .unwrap();
+        }
+        assert_eq!(resolver.inner().0.lookups(), 1);
+    }
+
+    #[test]
+    fn clamps_ttl() {
+        let config = CacheConfig {
+            max_ttl: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let resolver =
+            CachingResolver::with_config(Counting::new(Duration::from_secs(3600)), config);
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        sleep(Duration::from_millis(30));
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        assert_eq!(resolver.inner().lookups(), 2);
+
+        let config = CacheConfig {
+            min_ttl: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let resolver = CachingResolver::with_config(Counting::new(Duration::ZERO), config);
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        assert_eq!(resolver.inner().lookups(), 1);
+
+        // a maximum below the minimum wins instead of panicking
+        let config = CacheConfig {
+            min_ttl: Duration::from_secs(60),
+            max_ttl: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let resolver =
+            CachingResolver::with_config(Counting::new(Duration::from_secs(3600)), config);
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        sleep(Duration::from_millis(30));
+        resolver
+            .resolve_specific(&name("example.com"), QueryType::A)
+            .unwrap();
+        assert_eq!(resolver.inner().lookups(), 2);
+    }
+
+    #[test]
+    fn negative_caching() {
+        let resolver = CachingResolver::new(Counting::new(Duration::from_secs(60)));
+        for _ in 0..3 {
+            assert!(matches!(
+                resolver.resolve_specific(&name("missing.example"), QueryType::A),
+                Err(ResolveError::NxDomain { .. })
+            ));
+        }
+        assert_eq!(resolver.inner().lookups(), 1);
+
+        // the TTL of the SOA record is preferred, capped by the config
+        let resolver = CachingResolver::new(Counting::new(Duration::from_millis(20)));
+        let _ = resolver.resolve_specific(&name("soa.example"), QueryType::A);
+        let _ = resolver.resolve_specific(&name("soa.example"), QueryType::A);
+        assert_eq!(resolver.inner().lookups(), 1);
+        sleep(Duration::from_millis(30));
+        let _ = resolver.resolve_specific(&name("soa.example"), QueryType::A);
+        assert_eq!(resolver.inner().lookups(), 2);
+
+        let config = CacheConfig {
+            negative_ttl: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let resolver =
+            CachingResolver::with_config(Counting::new(Duration::from_secs(3600)), config);
+        let _ = resolver.resolve_specific(&name("soa.example"), QueryType::A);
+        sleep(Duration::from_millis(30));
+        let _ = resolver.resolve_specific(&name("soa.example"), QueryType::A);
+        assert_eq!(resolver.inner().lookups(), 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let config = CacheConfig {
+            capacity: 2,
+            ..Default::default()
+        };
+        let resolver = CachingResolver::with_config(Counting::new(Duration::from_secs(60)), config);
+        let _ = resolver.resolve_specific(&name("a.example"), QueryType::A);
+        let _ = resolver.resolve_specific(&name("b.example"), QueryType::A);
+        // touch a, so b is the oldest
+        let _ = resolver.resolve_specific(&name("a.example"), QueryType::A);
+        let _ = resolver.resolve_specific(&name("c.example"), QueryType::A);
+        assert_eq!(resolver.len(), 2);
+        assert_eq!(resolver.inner().lookups(), 3);
+
+        let _ = resolver.resolve_specific(&name("a.example"), QueryType::A);
+        assert_eq!(resolver.inner().lookups(), 3);
+        let _ = resolver.resolve_specific(&name("b.example"), QueryType::A);
+        assert_eq!(resolver.inner().lookups(), 4);
+    }
+
+    #[test]
+    fn clear_cache() {
+        let resolver = CachingResolver::new(Counting::new(Duration::from_secs(60)));
+        resolver.resolve(&name("example.com")).unwrap();
+        assert!(!resolver.is_empty());
+        assert_eq!(Resolver::clear_cache(&resolver), Ok(()));
+        assert!(resolver.is_empty());
+        resolver.resolve(&name("example.com")).unwrap();
+        assert_eq!(resolver.inner().lookups(), 4);
+    }
+}
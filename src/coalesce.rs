@@ -0,0 +1,347 @@
+//! Deduplication of concurrent identical lookups
+//!
+//! The first task asking for a name and record type performs the lookup,
+//! every task asking for the same pair while it is in flight waits for that
+//! result instead of hitting the backend again.
+
+use crate::{
+    lookup_each, AsyncResolver, DomainName, QueryType, Record, ResolveResult, SendAsyncResolver,
+};
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+/// Shares one upstream lookup between all concurrent callers
+pub struct CoalescingResolver<R> {
+    inner: R,
+    in_flight: Mutex<HashMap<Key, Arc<Flight>>>,
+}
+
+type Key = (DomainName, QueryType);
+
+/// One upstream lookup and the tasks waiting for it
+struct Flight {
+    state: Mutex<State>,
+}
+
+enum State {
+    Pending(Vec<Waker>),
+    Done(ResolveResult<Record>),
+    /// The leading task was dropped before it got an answer
+    Abandoned,
+}
+
+impl Flight {
+    fn finish(&self, state: State) {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), state);
+        if let State::Pending(wakers) = previous {
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+
+    /// `None` if the leader gave up and the lookup has to be retried
+    async fn wait(&self) -> Option<ResolveResult<Record>> {
+        poll_fn(|cx| match &mut *self.state.lock().unwrap() {
+            State::Pending(wakers) => {
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            State::Done(result) => Poll::Ready(Some(result.clone())),
+            State::Abandoned => Poll::Ready(None),
+        })
+        .await
+    }
+}
+
+/// Removes the flight once the leader is done or dropped
+struct Leader<'a, R> {
+    resolver: &'a CoalescingResolver<R>,
+    key: Key,
+    flight: Arc<Flight>,
+    finished: bool,
+}
+
+impl<R> Leader<'_, R> {
+    fn complete(mut self, result: &ResolveResult<Record>) {
+        self.finished = true;
+        self.resolver.remove(&self.key, &self.flight);
+        self.flight.finish(State::Done(result.clone()));
+    }
+}
+
+impl<R> Drop for Leader<'_, R> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.resolver.remove(&self.key, &self.flight);
+            self.flight.finish(State::Abandoned);
+        }
+    }
+}
+
+impl<R> CoalescingResolver<R> {
+    pub fn new(inner: R) -> Self {
+        CoalescingResolver {
+            inner,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// Number of distinct lookups currently waiting for the backend
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /// Join the lookup of `key`, the flag tells if the caller has to lead it
+    fn join(&self, key: &Key) -> (Arc<Flight>, bool) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        match in_flight.get(key) {
+            Some(flight) => (flight.clone(), false),
+            None => {
+                let flight = Arc::new(Flight {
+                    state: Mutex::new(State::Pending(Vec::new())),
+                });
+                in_flight.insert(key.clone(), flight.clone());
+                (flight, true)
+            }
+        }
+    }
+
+    fn remove(&self, key: &Key, flight: &Arc<Flight>) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(key).is_some_and(|f| Arc::ptr_eq(f, flight)) {
+            in_flight.remove(key);
+        }
+    }
+
+    /// Wait for the lookup of `name` and `query` in flight, or lead it with
+    /// `lookup` if there is none
+    async fn coalesced<F: Future<Output = ResolveResult<Record>>>(
+        &self,
+        name: &DomainName,
+        query: QueryType,
+        lookup: impl Fn() -> F,
+    ) -> ResolveResult<Record> {
+        let key = (name.clone(), query);
+        loop {
+            let (flight, leading) = self.join(&key);
+            if leading {
+                let leader = Leader {
+                    resolver: self,
+                    key,
+                    flight,
+                    finished: false,
+                };
+                let result = lookup().await;
+                leader.complete(&result);
+                return result;
+            }
+            if let Some(result) = flight.wait().await {
+                return result;
+            }
+        }
+    }
+}
+
+impl<R: AsyncResolver> AsyncResolver for CoalescingResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.coalesced(name, query, || self.inner.resolve_specific(name, query))
+            .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        lookup_each(queries, |query| {
+            AsyncResolver::resolve_specific(self, name, query)
+        })
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.inner.clear_cache().await
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        self.inner.reload_system_config().await
+    }
+}
+
+impl<R: SendAsyncResolver> SendAsyncResolver for CoalescingResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.coalesced(name, query, || {
+            SendAsyncResolver::resolve_specific(&self.inner, name, query)
+        })
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        lookup_each(queries, |query| {
+            SendAsyncResolver::resolve_specific(self, name, query)
+        })
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        SendAsyncResolver::clear_cache(&self.inner).await
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        SendAsyncResolver::reload_system_config(&self.inner).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::{collect_many, RecordData, ResolveError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Answers `example.com` once the test opens the gate and counts the
+    /// lookups
+    #[derive(Default)]
+    struct Gated {
+        lookups: AtomicUsize,
+        gate: Mutex<(bool, Vec<Waker>)>,
+    }
+
+    impl Gated {
+        fn open(&self) {
+            let mut gate = self.gate.lock().unwrap();
+            gate.0 = true;
+            gate.1.drain(..).for_each(Waker::wake);
+        }
+
+        async fn passed(&self) {
+            poll_fn(|cx| {
+                let mut gate = self.gate.lock().unwrap();
+                if gate.0 {
+                    return Poll::Ready(());
+                }
+                gate.1.push(cx.waker().clone());
+                Poll::Pending
+            })
+            .await
+        }
+
+        fn lookups(&self) -> usize {
+            self.lookups.load(Ordering::SeqCst)
+        }
+    }
+
+    impl AsyncResolver for Gated {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            self.passed().await;
+            match (name.as_str(), query) {
+                ("example.com", QueryType::A) => Ok(Record::new(
+                    name.clone(),
+                    Duration::from_secs(60),
+                    RecordData::IpAddr(vec!["192.0.2.1".parse().unwrap()]),
+                )),
+                _ => Err(ResolveError::NxDomain {
+                    name: name.clone(),
+                    query,
+                    ttl: None,
+                }),
+            }
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            let mut results = Vec::new();
+            for query in queries {
+                results.push(self.resolve_specific(name, query).await);
+            }
+            collect_many(results.into_iter())
+        }
+    }
+
+    /// Start `tasks` lookups of `name` on one executor, open the gate once
+    /// all of them wait and return their results
+    fn concurrent(
+        resolver: &CoalescingResolver<Gated>,
+        name: &str,
+        tasks: usize,
+    ) -> Vec<ResolveResult<Record>> {
+        let name: DomainName = name.parse().unwrap();
+        block_on(async {
+            let mut lookups: Vec<_> = (0..tasks)
+                .map(|_| Box::pin(resolver.resolve_specific(&name, QueryType::A)))
+                .collect();
+            poll_fn(|cx| {
+                for lookup in &mut lookups {
+                    assert!(lookup.as_mut().poll(cx).is_pending());
+                }
+                Poll::Ready(())
+            })
+            .await;
+            resolver.inner().open();
+            let mut results = Vec::new();
+            for lookup in lookups {
+                results.push(lookup.await);
+            }
+            results
+        })
+    }
+
+    #[test]
+    fn shares_one_lookup() {
+        let resolver = CoalescingResolver::new(Gated::default());
+        let results = concurrent(&resolver, "example.com", 16);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(resolver.inner().lookups(), 1);
+        assert_eq!(resolver.in_flight(), 0);
+    }
+
+    #[test]
+    fn shares_errors() {
+        let resolver = CoalescingResolver::new(Gated::default());
+        let results = concurrent(&resolver, "missing.example", 16);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(ResolveError::NxDomain { .. }))));
+        assert_eq!(resolver.inner().lookups(), 1);
+    }
+
+    #[test]
+    fn waiter_takes_over_abandoned_lookup() {
+        let resolver = CoalescingResolver::new(Gated::default());
+        let name: DomainName = "example.com".parse().unwrap();
+        block_on(async {
+            let mut leader = Box::pin(resolver.resolve_specific(&name, QueryType::A));
+            let mut waiter = Box::pin(resolver.resolve_specific(&name, QueryType::A));
+            poll_fn(|cx| {
+                assert!(leader.as_mut().poll(cx).is_pending());
+                assert!(waiter.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            drop(leader);
+            resolver.inner().open();
+            assert!(waiter.await.is_ok());
+        });
+        assert_eq!(resolver.inner().lookups(), 2);
+    }
+}
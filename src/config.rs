@@ -0,0 +1,304 @@
+//! The system resolver configuration from `/etc/resolv.conf`
+//!
+//! [`ResolverConfig::parse`] understands the keywords of resolv.conf(5) that
+//! matter to a stub resolver and is as forgiving as the glibc parser:
+//! unknown keywords and invalid values are skipped, numeric options are
+//! capped at the glibc limits. [`SystemConfig`] keeps the parsed file and
+//! re-reads it only after it changed on disk, which is what the default
+//! `reload_system_config` of both resolver traits relies on.
+
+use crate::DomainName;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// At most this many name servers are used, like `MAXNS` of glibc
+pub const MAX_NAMESERVERS: usize = 3;
+const MAX_NDOTS: usize = 15;
+const MAX_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_ATTEMPTS: usize = 5;
+
+/// The resolver settings of resolv.conf(5)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolverConfig {
+    /// `nameserver`, asked in order unless `rotate` is set
+    pub nameservers: Vec<SocketAddr>,
+    /// `search` or `domain`, whichever came last
+    pub search: Vec<DomainName>,
+    /// Names with at least this many dots are tried as is first
+    pub ndots: usize,
+    /// How long to wait for a single answer, the stub resolver waits at
+    /// least a second
+    pub timeout: Duration,
+    /// How often the whole list of name servers is tried
+    pub attempts: usize,
+    /// Spread the load by starting with a different server every query
+    pub rotate: bool,
+    /// Announce EDNS(0) support in queries
+    pub edns0: bool,
+    /// Do not send the A and AAAA query at the same time
+    pub single_request: bool,
+    /// Trust the AD bit of the name servers and request it
+    pub trust_ad: bool,
+}
+
+impl Default for ResolverConfig {
+    /// The glibc defaults, including the name server on localhost
+    fn default() -> Self {
+        ResolverConfig {
+            nameservers: vec![SocketAddr::new(IpAddr::from([127, 0, 0, 1]), 53)],
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+            edns0: false,
+            single_request: false,
+            trust_ad: false,
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Where the system configuration lives
+    pub const PATH: &'static str = "/etc/resolv.conf";
+
+    /// Parse the content of a resolv.conf file
+    pub fn parse(text: &str) -> Self {
+        let mut config = ResolverConfig {
+            nameservers: Vec::new(),
+            ..ResolverConfig::default()
+        };
+
+        for line in text.lines() {
+            let line = line.trim_start();
+            if line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    let address = words.next().and_then(parse_nameserver);
+                    if let Some(address) = address {
+                        if config.nameservers.len() < MAX_NAMESERVERS {
+                            config.nameservers.push(address);
+                        }
+                    }
+                }
+                Some("domain") => {
+                    config.search = words
+                        .next()
+                        .and_then(|domain| domain.parse().ok())
+                        .into_iter()
+                        .collect();
+                }
+                Some("search") => {
+                    config.search = words.filter_map(|domain| domain.parse().ok()).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        config.apply_option(option);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if config.nameservers.is_empty() {
+            config.nameservers = ResolverConfig::default().nameservers;
+        }
+        config
+    }
+
+    /// Read and parse the file at `path`, a missing file yields the defaults
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(ResolverConfig::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ResolverConfig::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read and parse [`ResolverConfig::PATH`]
+    pub fn system() -> io::Result<Self> {
+        ResolverConfig::from_file(Self::PATH)
+    }
+
+    fn apply_option(&mut self, option: &str) {
+        let (key, value) = match option.split_once(':') {
+            Some((key, value)) => (key, value.parse::<usize>().ok()),
+            None => (option, None),
+        };
+        match (key, value) {
+            ("ndots", Some(ndots)) => self.ndots = ndots.min(MAX_NDOTS),
+            ("timeout", Some(secs)) => {
+                self.timeout = Duration::from_secs(secs as u64).min(MAX_TIMEOUT)
+            }
+            ("attempts", Some(attempts)) => self.attempts = attempts.min(MAX_ATTEMPTS),
+            ("rotate", None) => self.rotate = true,
+            ("edns0", None) => self.edns0 = true,
+            ("single-request", None) => self.single_request = true,
+            ("trust-ad", None) => self.trust_ad = true,
+            _ => {}
+        }
+    }
+}
+
+/// An address with an optional numeric IPv6 zone, like `fe80::1%2`
+fn parse_nameserver(address: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 53));
+    }
+    let (ip, zone) = address.split_once('%')?;
+    let ip: Ipv6Addr = ip.parse().ok()?;
+    let scope = zone.parse().unwrap_or(0);
+    Some(SocketAddrV6::new(ip, 53, 0, scope).into())
+}
+
+/// What identifies a version of the file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    /// `None` if the file does not exist
+    fn of(path: &Path) -> io::Result<Option<Self>> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some(Stamp {
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            })),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Loaded {
+    stamp: Option<Stamp>,
+    config: Arc<ResolverConfig>,
+}
+
+/// A resolv.conf file that is parsed again once it changed
+///
+/// Changes are detected by modification time and size, so checking is
+/// cheap enough to do before every lookup.
+#[derive(Debug)]
+pub struct SystemConfig {
+    path: PathBuf,
+    loaded: Mutex<Loaded>,
+}
+
+impl SystemConfig {
+    /// Load the file at `path`
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let stamp = Stamp::of(&path)?;
+        let config = ResolverConfig::from_file(&path)?;
+        Ok(SystemConfig {
+            path,
+            loaded: Mutex::new(Loaded {
+                stamp,
+                config: Arc::new(config),
+            }),
+        })
+    }
+
+    /// Load [`ResolverConfig::PATH`]
+    pub fn system() -> io::Result<Self> {
+        SystemConfig::open(ResolverConfig::PATH)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The configuration as of the last load
+    pub fn config(&self) -> Arc<ResolverConfig> {
+        self.loaded.lock().unwrap().config.clone()
+    }
+
+    /// Parse the file again if it changed since the last load
+    ///
+    /// Returns true if the configuration was replaced.
+    pub fn reload(&self) -> io::Result<bool> {
+        let stamp = Stamp::of(&self.path)?;
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.stamp == stamp {
+            return Ok(false);
+        }
+        let config = ResolverConfig::from_file(&self.path)?;
+        let changed = *loaded.config != config;
+        *loaded = Loaded {
+            stamp,
+            config: Arc::new(config),
+        };
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_resolv_conf() {
+        let config = ResolverConfig::parse(
+            "# generated by NetworkManager\n\
+             domain corp.example\n\
+             search lab.example corp.example\n\
+             nameserver 192.0.2.53\n\
+             nameserver 2001:db8::53\n\
+             ; nameserver 192.0.2.99\n\
+             nameserver fe80::1%2\n\
+             nameserver 192.0.2.54\n\
+             sortlist 130.155.160.0/255.255.240.0\n\
+             options ndots:2 timeout:3 attempts:9 rotate edns0 single-request trust-ad\n",
+        );
+        assert_eq!(
+            config.nameservers,
+            [
+                "192.0.2.53:53".parse().unwrap(),
+                "[2001:db8::53]:53".parse().unwrap(),
+                "[fe80::1%2]:53".parse::<SocketAddr>().unwrap(),
+            ]
+        );
+        let search: Vec<_> = config.search.iter().map(|d| d.as_str()).collect();
+        assert_eq!(search, ["lab.example", "corp.example"]);
+        assert_eq!(config.ndots, 2);
+        assert_eq!(config.timeout, Duration::from_secs(3));
+        assert_eq!(config.attempts, MAX_ATTEMPTS);
+        assert!(config.rotate && config.edns0 && config.single_request && config.trust_ad);
+    }
+
+    #[test]
+    fn defaults() {
+        let config = ResolverConfig::parse("nameserver not-an-address\noptions ndots:x\n");
+        assert_eq!(config, ResolverConfig::default());
+        let config = ResolverConfig::parse("search a.example\ndomain b.example\n");
+        assert_eq!(config.search, ["b.example".parse::<DomainName>().unwrap()]);
+    }
+
+    #[test]
+    fn reloads_after_change() {
+        let path =
+            std::env::temp_dir().join(format!("resolver_types-{}-resolv.conf", std::process::id()));
+        fs::write(&path, "nameserver 192.0.2.1\n").unwrap();
+        let system = SystemConfig::open(&path).unwrap();
+        assert!(!system.reload().unwrap());
+
+        fs::write(&path, "nameserver 192.0.2.2\noptions ndots:3\n").unwrap();
+        assert!(system.reload().unwrap());
+        assert_eq!(system.config().ndots, 3);
+
+        fs::remove_file(&path).unwrap();
+        assert!(system.reload().unwrap());
+        assert_eq!(*system.config(), ResolverConfig::default());
+    }
+}
@@ -0,0 +1,472 @@
+//! Object safe resolver traits to pick a backend at runtime
+//!
+//! [`Resolver`] and [`AsyncResolver`] take a generic iterator in
+//! `resolve_many` and can not be made into trait objects. Every resolver
+//! also implements [`DynResolver`], every [`SendAsyncResolver`]
+//! [`DynAsyncResolver`]. They take a slice instead and the trait objects
+//! implement the generic traits again:
+//!
+//! ```ignore
+//! let backend: Box<dyn DynResolver> = match name {
+//!     "hosts" => Box::new(HostsFileResolver::system()),
+//!     _ => Box::new(StubResolver::system()?),
+//! };
+//! let resolver = CachingResolver::new(backend);
+//! ```
+//!
+//! The methods carry a `dyn_` prefix so they do not clash with the generic
+//! traits when both are in scope.
+
+use crate::{
+    AsyncResolver, DomainName, QueryType, Record, ResolveResult, Resolver, SendAsyncResolver,
+    SystemConfig, TtlEntry,
+};
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// The object safe form of [`Resolver`]
+#[allow(clippy::result_unit_err)]
+pub trait DynResolver: Send + Sync {
+    fn dyn_resolve(&self, name: &DomainName) -> ResolveResult<IpAddr>;
+    fn dyn_resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>>;
+    fn dyn_reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>>;
+    fn dyn_resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record>;
+    fn dyn_resolve_many(
+        &self,
+        name: &DomainName,
+        queries: &[QueryType],
+    ) -> ResolveResult<Vec<Record>>;
+    fn dyn_clear_cache(&self) -> Result<(), ()>;
+    fn dyn_system_config(&self) -> Option<&SystemConfig>;
+    fn dyn_reload_system_config(&self) -> Result<(), ()>;
+}
+
+impl<R: Resolver + Send + Sync> DynResolver for R {
+    fn dyn_resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
+        self.resolve(name)
+    }
+
+    fn dyn_resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+        self.resolve_all(name)
+    }
+
+    fn dyn_reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+        self.reverse(address)
+    }
+
+    fn dyn_resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.resolve_specific(name, query)
+    }
+
+    fn dyn_resolve_many(
+        &self,
+        name: &DomainName,
+        queries: &[QueryType],
+    ) -> ResolveResult<Vec<Record>> {
+        self.resolve_many(name, queries.iter().copied())
+    }
+
+    fn dyn_clear_cache(&self) -> Result<(), ()> {
+        self.clear_cache()
+    }
+
+    fn dyn_system_config(&self) -> Option<&SystemConfig> {
+        self.system_config()
+    }
+
+    fn dyn_reload_system_config(&self) -> Result<(), ()> {
+        self.reload_system_config()
+    }
+}
+
+impl Resolver for dyn DynResolver + '_ {
+    fn resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
+        self.dyn_resolve(name)
+    }
+
+    fn resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+        self.dyn_resolve_all(name)
+    }
+
+    fn reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+        self.dyn_reverse(address)
+    }
+
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.dyn_resolve_specific(name, query)
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        self.dyn_resolve_many(name, &queries)
+    }
+
+    fn clear_cache(&self) -> Result<(), ()> {
+        self.dyn_clear_cache()
+    }
+
+    fn system_config(&self) -> Option<&SystemConfig> {
+        self.dyn_system_config()
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        self.dyn_reload_system_config()
+    }
+}
+
+/// A boxed future as returned by [`DynAsyncResolver`]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The object safe form of [`SendAsyncResolver`]
+///
+/// Every call allocates the returned future, generic code should prefer
+/// [`AsyncResolver`] or [`SendAsyncResolver`].
+#[allow(clippy::result_unit_err)]
+pub trait DynAsyncResolver: Send + Sync {
+    fn dyn_resolve<'a>(&'a self, name: &'a DomainName) -> BoxFuture<'a, ResolveResult<IpAddr>>;
+    fn dyn_resolve_all<'a>(
+        &'a self,
+        name: &'a DomainName,
+    ) -> BoxFuture<'a, ResolveResult<Vec<TtlEntry<IpAddr>>>>;
+    fn dyn_reverse(&self, address: IpAddr) -> BoxFuture<'_, ResolveResult<Vec<DomainName>>>;
+    fn dyn_resolve_specific<'a>(
+        &'a self,
+        name: &'a DomainName,
+        query: QueryType,
+    ) -> BoxFuture<'a, ResolveResult<Record>>;
+    fn dyn_resolve_many<'a>(
+        &'a self,
+        name: &'a DomainName,
+        queries: &'a [QueryType],
+    ) -> BoxFuture<'a, ResolveResult<Vec<Record>>>;
+    fn dyn_clear_cache(&self) -> BoxFuture<'_, Result<(), ()>>;
+    fn dyn_system_config(&self) -> Option<&SystemConfig>;
+    fn dyn_reload_system_config(&self) -> BoxFuture<'_, Result<(), ()>>;
+}
+
+impl<R: SendAsyncResolver> DynAsyncResolver for R {
+    fn dyn_resolve<'a>(&'a self, name: &'a DomainName) -> BoxFuture<'a, ResolveResult<IpAddr>> {
+        Box::pin(SendAsyncResolver::resolve(self, name))
+    }
+
+    fn dyn_resolve_all<'a>(
+        &'a self,
+        name: &'a DomainName,
+    ) -> BoxFuture<'a, ResolveResult<Vec<TtlEntry<IpAddr>>>> {
+        Box::pin(SendAsyncResolver::resolve_all(self, name))
+    }
+
+    fn dyn_reverse(&self, address: IpAddr) -> BoxFuture<'_, ResolveResult<Vec<DomainName>>> {
+        Box::pin(SendAsyncResolver::reverse(self, address))
+    }
+
+    fn dyn_resolve_specific<'a>(
+        &'a self,
+        name: &'a DomainName,
+        query: QueryType,
+    ) -> BoxFuture<'a, ResolveResult<Record>> {
+        Box::pin(SendAsyncResolver::resolve_specific(self, name, query))
+    }
+
+    fn dyn_resolve_many<'a>(
+        &'a self,
+        name: &'a DomainName,
+        queries: &'a [QueryType],
+    ) -> BoxFuture<'a, ResolveResult<Vec<Record>>> {
+        Box::pin(SendAsyncResolver::resolve_many(
+            self,
+            name,
+            queries.iter().copied(),
+        ))
+    }
+
+    fn dyn_clear_cache(&self) -> BoxFuture<'_, Result<(), ()>> {
+        Box::pin(SendAsyncResolver::clear_cache(self))
+    }
+
+    fn dyn_system_config(&self) -> Option<&SystemConfig> {
+        self.system_config()
+    }
+
+    fn dyn_reload_system_config(&self) -> BoxFuture<'_, Result<(), ()>> {
+        Box::pin(SendAsyncResolver::reload_system_config(self))
+    }
+}
+
+impl AsyncResolver for dyn DynAsyncResolver + '_ {
+    async fn resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
+        self.dyn_resolve(name).await
+    }
+
+    async fn resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+        self.dyn_resolve_all(name).await
+    }
+
+    async fn reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+        self.dyn_reverse(address).await
+    }
+
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.dyn_resolve_specific(name, query).await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        self.dyn_resolve_many(name, &queries).await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.dyn_clear_cache().await
+    }
+
+    fn system_config(&self) -> Option<&SystemConfig> {
+        self.dyn_system_config()
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        self.dyn_reload_system_config().await
+    }
+}
+
+impl SendAsyncResolver for dyn DynAsyncResolver + '_ {
+    async fn resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
+        self.dyn_resolve(name).await
+    }
+
+    async fn resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+        self.dyn_resolve_all(name).await
+    }
+
+    async fn reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+        self.dyn_reverse(address).await
+    }
+
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.dyn_resolve_specific(name, query).await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        self.dyn_resolve_many(name, &queries).await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.dyn_clear_cache().await
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        self.dyn_reload_system_config().await
+    }
+}
+
+/// Forward every method of `Resolver` to the pointee
+macro_rules! forward_resolver {
+    ($pointer:ident) => {
+        impl<R: Resolver + ?Sized> Resolver for $pointer<R> {
+            fn resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
+                (**self).resolve(name)
+            }
+
+            fn resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+                (**self).resolve_all(name)
+            }
+
+            fn reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+                (**self).reverse(address)
+            }
+
+            fn resolve_specific(
+                &self,
+                name: &DomainName,
+                query: QueryType,
+            ) -> ResolveResult<Record> {
+                (**self).resolve_specific(name, query)
+            }
+
+            fn resolve_many<I: Iterator<Item = QueryType>>(
+                &self,
+                name: &DomainName,
+                queries: I,
+            ) -> ResolveResult<Vec<Record>> {
+                (**self).resolve_many(name, queries)
+            }
+
+            fn clear_cache(&self) -> Result<(), ()> {
+                (**self).clear_cache()
+            }
+
+            fn system_config(&self) -> Option<&SystemConfig> {
+                (**self).system_config()
+            }
+
+            fn reload_system_config(&self) -> Result<(), ()> {
+                (**self).reload_system_config()
+            }
+        }
+
+        impl<R: AsyncResolver + ?Sized> AsyncResolver for $pointer<R> {
+            async fn resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
+                (**self).resolve(name).await
+            }
+
+            async fn resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+                (**self).resolve_all(name).await
+            }
+
+            async fn reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+                (**self).reverse(address).await
+            }
+
+            async fn resolve_specific(
+                &self,
+                name: &DomainName,
+                query: QueryType,
+            ) -> ResolveResult<Record> {
+                (**self).resolve_specific(name, query).await
+            }
+
+            async fn resolve_many<I: Iterator<Item = QueryType>>(
+                &self,
+                name: &DomainName,
+                queries: I,
+            ) -> ResolveResult<Vec<Record>> {
+                (**self).resolve_many(name, queries).await
+            }
+
+            async fn clear_cache(&self) -> Result<(), ()> {
+                (**self).clear_cache().await
+            }
+
+            fn system_config(&self) -> Option<&SystemConfig> {
+                (**self).system_config()
+            }
+
+            async fn reload_system_config(&self) -> Result<(), ()> {
+                (**self).reload_system_config().await
+            }
+        }
+
+        impl<R: SendAsyncResolver + ?Sized> SendAsyncResolver for $pointer<R> {
+            fn resolve(
+                &self,
+                name: &DomainName,
+            ) -> impl Future<Output = ResolveResult<IpAddr>> + Send {
+                SendAsyncResolver::resolve(&**self, name)
+            }
+
+            fn resolve_all(
+                &self,
+                name: &DomainName,
+            ) -> impl Future<Output = ResolveResult<Vec<TtlEntry<IpAddr>>>> + Send {
+                SendAsyncResolver::resolve_all(&**self, name)
+            }
+
+            fn reverse(
+                &self,
+                address: IpAddr,
+            ) -> impl Future<Output = ResolveResult<Vec<DomainName>>> + Send {
+                SendAsyncResolver::reverse(&**self, address)
+            }
+
+            fn resolve_specific(
+                &self,
+                name: &DomainName,
+                query: QueryType,
+            ) -> impl Future<Output = ResolveResult<Record>> + Send {
+                SendAsyncResolver::resolve_specific(&**self, name, query)
+            }
+
+            fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+                &self,
+                name: &DomainName,
+                queries: I,
+            ) -> impl Future<Output = ResolveResult<Vec<Record>>> + Send {
+                SendAsyncResolver::resolve_many(&**self, name, queries)
+            }
+
+            fn clear_cache(&self) -> impl Future<Output = Result<(), ()>> + Send {
+                SendAsyncResolver::clear_cache(&**self)
+            }
+
+            fn reload_system_config(&self) -> impl Future<Output = Result<(), ()>> + Send {
+                SendAsyncResolver::reload_system_config(&**self)
+            }
+        }
+    };
+}
+
+forward_resolver!(Box);
+forward_resolver!(Arc);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::{AsyncFromSync, CachingResolver, FallbackPolicy, FallbackResolver};
+    use crate::{HostsFile, HostsFileResolver};
+
+    fn hosts(text: &str) -> HostsFileResolver {
+        HostsFileResolver::new(HostsFile::parse(text))
+    }
+
+    /// What a configuration file would choose between
+    fn backend(kind: &str) -> Box<dyn DynResolver> {
+        match kind {
+            "hosts" => Box::new(hosts("192.0.2.1 www.example.com\n")),
+            "chain" => Box::new(FallbackResolver::with_policy(
+                hosts("192.0.2.2 other.example\n"),
+                hosts("192.0.2.3 www.example.com\n"),
+                FallbackPolicy::always(),
+            )),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn picks_backends_at_runtime() {
+        let name = "www.example.com".parse().unwrap();
+        for (kind, address) in [("hosts", [192, 0, 2, 1]), ("chain", [192, 0, 2, 3])] {
+            let resolver = backend(kind);
+            assert_eq!(resolver.resolve(&name).unwrap(), IpAddr::from(address));
+            assert_eq!(resolver.reverse(address.into()).unwrap(), [name.to_fqdn()]);
+        }
+
+        let cached = CachingResolver::new(backend("hosts"));
+        assert_eq!(cached.resolve(&name).unwrap(), IpAddr::from([192, 0, 2, 1]));
+    }
+
+    #[test]
+    fn async_trait_objects() {
+        let backends: Vec<Arc<dyn DynAsyncResolver>> = vec![
+            Arc::new(AsyncFromSync::new(hosts("192.0.2.1 www.example.com\n"))),
+            Arc::new(CachingResolver::new(AsyncFromSync::new(hosts(
+                "192.0.2.2 www.example.com\n",
+            )))),
+        ];
+        let name = "www.example.com".parse().unwrap();
+        let addresses: Vec<_> = backends
+            .iter()
+            .map(|backend| block_on(AsyncResolver::resolve(backend, &name)).unwrap())
+            .collect();
+        assert_eq!(
+            addresses,
+            [IpAddr::from([192, 0, 2, 1]), IpAddr::from([192, 0, 2, 2])]
+        );
+        assert_eq!(block_on(AsyncResolver::clear_cache(&backends[0])), Err(()));
+        assert_eq!(block_on(AsyncResolver::clear_cache(&backends[1])), Ok(()));
+    }
+}
@@ -0,0 +1,293 @@
+//! Errors of a lookup
+//!
+//! The variants separate answers that are final (the name or the record
+//! does not exist) from failures where retrying or asking another server may
+//! help, see [`ResolveError::is_retryable`].
+
+use crate::{DomainName, QueryType};
+use std::fmt;
+use std::time::Duration;
+
+pub type ResolveResult<T> = Result<T, ResolveError>;
+
+/// The RCODE of a DNS response (RFC 1035 section 4.1.1, RFC 6895)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseCode {
+    NoError,
+    /// The server could not interpret the query
+    FormErr,
+    /// The server failed to process the query
+    ServFail,
+    /// The name does not exist
+    NXDomain,
+    /// The server does not support the kind of query
+    NotImp,
+    /// The server refused to answer for policy reasons
+    Refused,
+    YXDomain,
+    YXRRSet,
+    NXRRSet,
+    NotAuth,
+    NotZone,
+    Other(u16),
+}
+
+impl From<u16> for ResponseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormErr,
+            2 => ResponseCode::ServFail,
+            3 => ResponseCode::NXDomain,
+            4 => ResponseCode::NotImp,
+            5 => ResponseCode::Refused,
+            6 => ResponseCode::YXDomain,
+            7 => ResponseCode::YXRRSet,
+            8 => ResponseCode::NXRRSet,
+            9 => ResponseCode::NotAuth,
+            10 => ResponseCode::NotZone,
+            other => ResponseCode::Other(other),
+        }
+    }
+}
+
+impl From<ResponseCode> for u16 {
+    fn from(code: ResponseCode) -> Self {
+        match code {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormErr => 1,
+            ResponseCode::ServFail => 2,
+            ResponseCode::NXDomain => 3,
+            ResponseCode::NotImp => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::YXDomain => 6,
+            ResponseCode::YXRRSet => 7,
+            ResponseCode::NXRRSet => 8,
+            ResponseCode::NotAuth => 9,
+            ResponseCode::NotZone => 10,
+            ResponseCode::Other(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseCode::Other(code) => write!(f, "RCODE{}", code),
+            known => write!(f, "{}", format!("{:?}", known).to_uppercase()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    /// Some lower stack Input/Output Error
+    IO(std::io::Error),
+    /// The name does not exist (NXDOMAIN)
+    ///
+    /// `ttl` is how long the answer may be cached, the smaller of the TTL
+    /// and the MINIMUM field of the SOA record in the authority section
+    /// (RFC 2308 section 5), if the answer had one.
+    NxDomain {
+        name: DomainName,
+        query: QueryType,
+        ttl: Option<Duration>,
+    },
+    /// The name exists but has no records of the queried type (NODATA)
+    ///
+    /// `ttl` is derived from the SOA record like for `NxDomain`.
+    NoData {
+        name: DomainName,
+        query: QueryType,
+        ttl: Option<Duration>,
+    },
+    /// The server answered with an error code like SERVFAIL or REFUSED
+    Server {
+        rcode: ResponseCode,
+        name: DomainName,
+        query: QueryType,
+    },
+    /// No answer arrived in time
+    Timeout { name: DomainName, query: QueryType },
+    /// The response could not be parsed or did not match the query
+    Malformed(String),
+}
+
+impl ResolveError {
+    /// True if retrying, possibly at another server, may give an answer
+    ///
+    /// NXDOMAIN and NODATA are final answers, so are the server errors that
+    /// reject the query itself like FORMERR and NOTIMP.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ResolveError::IO(_) | ResolveError::Timeout { .. } | ResolveError::Malformed(_) => true,
+            ResolveError::Server { rcode, .. } => {
+                matches!(rcode, ResponseCode::ServFail | ResponseCode::Refused)
+            }
+            ResolveError::NxDomain { .. } | ResolveError::NoData { .. } => false,
+        }
+    }
+
+    /// True for NXDOMAIN and NODATA which may be cached (RFC 2308)
+    pub fn is_negative(&self) -> bool {
+        matches!(
+            self,
+            ResolveError::NxDomain { .. } | ResolveError::NoData { .. }
+        )
+    }
+
+    /// How long a negative answer may be cached according to the server
+    pub fn negative_ttl(&self) -> Option<Duration> {
+        match self {
+            ResolveError::NxDomain { ttl, .. } | ResolveError::NoData { ttl, .. } => *ttl,
+            _ => None,
+        }
+    }
+
+    /// The response code, if a server answered
+    pub fn rcode(&self) -> Option<ResponseCode> {
+        match self {
+            ResolveError::NxDomain { .. } => Some(ResponseCode::NXDomain),
+            ResolveError::NoData { .. } => Some(ResponseCode::NoError),
+            ResolveError::Server { rcode, .. } => Some(*rcode),
+            _ => None,
+        }
+    }
+
+    /// The queried name, if known
+    pub fn name(&self) -> Option<&DomainName> {
+        match self {
+            ResolveError::NxDomain { name, .. }
+            | ResolveError::NoData { name, .. }
+            | ResolveError::Server { name, .. }
+            | ResolveError::Timeout { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The queried record type, if known
+    pub fn query(&self) -> Option<QueryType> {
+        match self {
+            ResolveError::NxDomain { query, .. }
+            | ResolveError::NoData { query, .. }
+            | ResolveError::Server { query, .. }
+            | ResolveError::Timeout { query, .. } => Some(*query),
+            _ => None,
+        }
+    }
+}
+
+/// `std::io::Error` can not be cloned, the copy keeps its kind and message
+impl Clone for ResolveError {
+    fn clone(&self) -> Self {
+        match self {
+            ResolveError::IO(e) => ResolveError::IO(std::io::Error::new(e.kind(), e.to_string())),
+            ResolveError::NxDomain { name, query, ttl } => ResolveError::NxDomain {
+                name: name.clone(),
+                query: *query,
+                ttl: *ttl,
+            },
+            ResolveError::NoData { name, query, ttl } => ResolveError::NoData {
+                name: name.clone(),
+                query: *query,
+                ttl: *ttl,
+            },
+            ResolveError::Server { rcode, name, query } => ResolveError::Server {
+                rcode: *rcode,
+                name: name.clone(),
+                query: *query,
+            },
+            ResolveError::Timeout { name, query } => ResolveError::Timeout {
+                name: name.clone(),
+                query: *query,
+            },
+            ResolveError::Malformed(reason) => ResolveError::Malformed(reason.clone()),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::IO(e) => write!(f, "i/o error: {}", e),
+            ResolveError::NxDomain { name, .. } => write!(f, "{} does not exist", name),
+            ResolveError::NoData { name, query, .. } => {
+                write!(f, "{} has no {:?} records", name, query)
+            }
+            ResolveError::Server { rcode, name, query } => {
+                write!(f, "{} while resolving {:?} {}", rcode, query, name)
+            }
+            ResolveError::Timeout { name, query } => {
+                write!(f, "timeout while resolving {:?} {}", query, name)
+            }
+            ResolveError::Malformed(reason) => write!(f, "malformed response: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResolveError::IO(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ResolveError {
+    fn from(e: std::io::Error) -> Self {
+        ResolveError::IO(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_policy() {
+        let name: DomainName = "example.com".parse().unwrap();
+        let query = QueryType::A;
+        let server = |rcode| ResolveError::Server {
+            rcode,
+            name: name.clone(),
+            query,
+        };
+        assert!(server(ResponseCode::ServFail).is_retryable());
+        assert!(server(ResponseCode::Refused).is_retryable());
+        assert!(!server(ResponseCode::NotImp).is_retryable());
+        assert!(!ResolveError::NxDomain {
+            name: name.clone(),
+            query,
+            ttl: None,
+        }
+        .is_retryable());
+        assert!(
+            ResolveError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+                .is_retryable()
+        );
+        let no_data = ResolveError::NoData {
+            name: name.clone(),
+            query,
+            ttl: None,
+        };
+        assert!(no_data.is_negative());
+        assert_eq!(no_data.rcode(), Some(ResponseCode::NoError));
+        assert_eq!(no_data.name(), Some(&name));
+    }
+
+    #[test]
+    fn display() {
+        let error = ResolveError::Server {
+            rcode: ResponseCode::ServFail,
+            name: "example.com.".parse().unwrap(),
+            query: QueryType::AAAA,
+        };
+        assert_eq!(
+            error.to_string(),
+            "SERVFAIL while resolving AAAA example.com."
+        );
+        assert_eq!(ResponseCode::from(23).to_string(), "RCODE23");
+        assert_eq!(u16::from(ResponseCode::from(5)), 5);
+    }
+}
@@ -0,0 +1,603 @@
+//! Ask a second resolver when the first one fails
+//!
+//! [`FallbackPolicy`] decides which errors of the primary resolver are worth
+//! asking the secondary one. Longer chains are built by nesting, for example
+//! a hosts file in front of two name servers:
+//!
+//! ```ignore
+//! FallbackResolver::with_policy(
+//!     hosts,
+//!     FallbackResolver::new(primary_dns, secondary_dns),
+//!     FallbackPolicy::always(),
+//! )
+//! ```
+//!
+//! [`FallbackList`] takes the resolvers as a list instead, all of one type
+//! or boxed as [`DynResolver`](crate::DynResolver):
+//!
+//! ```ignore
+//! let backends: Vec<Box<dyn DynResolver>> = vec![Box::new(hosts), Box::new(dns)];
+//! FallbackList::with_policy(backends, FallbackPolicy::always())
+//! ```
+
+use crate::{
+    AsyncResolver, DomainName, QueryType, Record, ResolveError, ResolveResult, Resolver,
+    SendAsyncResolver,
+};
+use std::future::Future;
+
+/// The errors that make [`FallbackResolver`] ask the next resolver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FallbackPolicy {
+    pub io: bool,
+    pub timeout: bool,
+    pub malformed: bool,
+    /// Any response code but NXDOMAIN, like SERVFAIL or REFUSED
+    pub server_error: bool,
+    pub nx_domain: bool,
+    pub no_data: bool,
+}
+
+impl Default for FallbackPolicy {
+    /// Fall through on failures but trust negative answers
+    fn default() -> Self {
+        FallbackPolicy {
+            io: true,
+            timeout: true,
+            malformed: true,
+            server_error: true,
+            nx_domain: false,
+            no_data: false,
+        }
+    }
+}
+
+impl FallbackPolicy {
+    /// Fall through on every error, for resolvers that only know some names
+    /// like a hosts file
+    pub fn always() -> Self {
+        FallbackPolicy {
+            nx_domain: true,
+            no_data: true,
+            ..FallbackPolicy::default()
+        }
+    }
+
+    pub fn falls_through(&self, error: &ResolveError) -> bool {
+        match error {
+            ResolveError::IO(_) => self.io,
+            ResolveError::Timeout { .. } => self.timeout,
+            ResolveError::Malformed(_) => self.malformed,
+            ResolveError::Server { .. } => self.server_error,
+            ResolveError::NxDomain { .. } => self.nx_domain,
+            ResolveError::NoData { .. } => self.no_data,
+        }
+    }
+}
+
+/// Asks `primary` and, if its error falls through, `secondary`
+///
+/// If both fail the error of `secondary` is returned. `clear_cache` and
+/// `reload_system_config` go to both and succeed if one of them did. Longer
+/// chains are nested pairs or a [`FallbackList`].
+#[derive(Debug, Clone)]
+pub struct FallbackResolver<P, S> {
+    primary: P,
+    secondary: S,
+    policy: FallbackPolicy,
+}
+
+impl<P, S> FallbackResolver<P, S> {
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self::with_policy(primary, secondary, FallbackPolicy::default())
+    }
+
+    pub fn with_policy(primary: P, secondary: S, policy: FallbackPolicy) -> Self {
+        FallbackResolver {
+            primary,
+            secondary,
+            policy,
+        }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    pub fn policy(&self) -> &FallbackPolicy {
+        &self.policy
+    }
+
+    /// The answer of `primary`, or of `secondary` if its error falls through
+    async fn fall_back<T, F: Future<Output = ResolveResult<T>>>(
+        &self,
+        primary: impl Future<Output = ResolveResult<T>>,
+        secondary: impl FnOnce() -> F,
+    ) -> ResolveResult<T> {
+        match primary.await {
+            Err(e) if self.policy.falls_through(&e) => secondary().await,
+            result => result,
+        }
+    }
+}
+
+/// Ok if any child implemented the operation
+fn either(primary: Result<(), ()>, secondary: Result<(), ()>) -> Result<(), ()> {
+    primary.or(secondary)
+}
+
+impl<P: Resolver, S: Resolver> Resolver for FallbackResolver<P, S> {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        match self.primary.resolve_specific(name, query) {
+            Err(e) if self.policy.falls_through(&e) => self.secondary.resolve_specific(name, query),
+            result => result,
+        }
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        match self.primary.resolve_many(name, queries.iter().copied()) {
+            Err(e) if self.policy.falls_through(&e) => {
+                self.secondary.resolve_many(name, queries.into_iter())
+            }
+            result => result,
+        }
+    }
+
+    fn clear_cache(&self) -> Result<(), ()> {
+        either(self.primary.clear_cache(), self.secondary.clear_cache())
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        either(
+            self.primary.reload_system_config(),
+            self.secondary.reload_system_config(),
+        )
+    }
+}
+
+impl<P: AsyncResolver, S: AsyncResolver> AsyncResolver for FallbackResolver<P, S> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.fall_back(self.primary.resolve_specific(name, query), || {
+            self.secondary.resolve_specific(name, query)
+        })
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        self.fall_back(
+            self.primary.resolve_many(name, queries.iter().copied()),
+            || self.secondary.resolve_many(name, queries.iter().copied()),
+        )
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        either(
+            self.primary.clear_cache().await,
+            self.secondary.clear_cache().await,
+        )
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        either(
+            self.primary.reload_system_config().await,
+            self.secondary.reload_system_config().await,
+        )
+    }
+}
+
+impl<P: SendAsyncResolver, S: SendAsyncResolver> SendAsyncResolver for FallbackResolver<P, S> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.fall_back(
+            SendAsyncResolver::resolve_specific(&self.primary, name, query),
+            || SendAsyncResolver::resolve_specific(&self.secondary, name, query),
+        )
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        self.fall_back(
+            SendAsyncResolver::resolve_many(&self.primary, name, queries.iter().copied()),
+            || SendAsyncResolver::resolve_many(&self.secondary, name, queries.iter().copied()),
+        )
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        either(
+            SendAsyncResolver::clear_cache(&self.primary).await,
+            SendAsyncResolver::clear_cache(&self.secondary).await,
+        )
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        either(
+            SendAsyncResolver::reload_system_config(&self.primary).await,
+            SendAsyncResolver::reload_system_config(&self.secondary).await,
+        )
+    }
+}
+
+/// Asks the resolvers in order until one answers or fails with an error
+/// that does not fall through
+///
+/// The error of the last resolver asked is returned, the ones before failed
+/// in a way the policy looks past. `clear_cache` and `reload_system_config`
+/// go to every resolver and succeed if one of them did.
+#[derive(Debug, Clone)]
+pub struct FallbackList<R> {
+    resolvers: Vec<R>,
+    policy: FallbackPolicy,
+}
+
+impl<R> FallbackList<R> {
+    /// Panics if `resolvers` is empty
+    pub fn new(resolvers: Vec<R>) -> Self {
+        Self::with_policy(resolvers, FallbackPolicy::default())
+    }
+
+    /// Panics if `resolvers` is empty
+    pub fn with_policy(resolvers: Vec<R>, policy: FallbackPolicy) -> Self {
+        assert!(!resolvers.is_empty(), "FallbackList needs a resolver");
+        FallbackList { resolvers, policy }
+    }
+
+    pub fn resolvers(&self) -> &[R] {
+        &self.resolvers
+    }
+
+    pub fn policy(&self) -> &FallbackPolicy {
+        &self.policy
+    }
+
+    /// The answer of the first resolver whose error does not fall through
+    fn fall_through<T>(&self, lookup: impl Fn(&R) -> ResolveResult<T>) -> ResolveResult<T> {
+        let mut result = lookup(&self.resolvers[0]);
+        for resolver in &self.resolvers[1..] {
+            match &result {
+                Err(e) if self.policy.falls_through(e) => result = lookup(resolver),
+                _ => break,
+            }
+        }
+        result
+    }
+
+    async fn fall_through_async<'a, T, F: Future<Output = ResolveResult<T>>>(
+        &'a self,
+        lookup: impl Fn(&'a R) -> F,
+    ) -> ResolveResult<T> {
+        let mut result = lookup(&self.resolvers[0]).await;
+        for resolver in &self.resolvers[1..] {
+            match &result {
+                Err(e) if self.policy.falls_through(e) => result = lookup(resolver).await,
+                _ => break,
+            }
+        }
+        result
+    }
+}
+
+impl<R: Resolver> Resolver for FallbackList<R> {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.fall_through(|resolver| resolver.resolve_specific(name, query))
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        self.fall_through(|resolver| resolver.resolve_many(name, queries.iter().copied()))
+    }
+
+    fn clear_cache(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for resolver in &self.resolvers {
+            result = either(resolver.clear_cache(), result);
+        }
+        result
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for resolver in &self.resolvers {
+            result = either(resolver.reload_system_config(), result);
+        }
+        result
+    }
+}
+
+impl<R: AsyncResolver> AsyncResolver for FallbackList<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.fall_through_async(|resolver| resolver.resolve_specific(name, query))
+            .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let queries = &queries;
+        self.fall_through_async(|resolver| resolver.resolve_many(name, queries.iter().copied()))
+            .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for resolver in &self.resolvers {
+            result = either(resolver.clear_cache().await, result);
+        }
+        result
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for resolver in &self.resolvers {
+            result = either(resolver.reload_system_config().await, result);
+        }
+        result
+    }
+}
+
+impl<R: SendAsyncResolver> SendAsyncResolver for FallbackList<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.fall_through_async(|resolver| {
+            SendAsyncResolver::resolve_specific(resolver, name, query)
+        })
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let queries = &queries;
+        self.fall_through_async(|resolver| {
+            SendAsyncResolver::resolve_many(resolver, name, queries.iter().copied())
+        })
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for resolver in &self.resolvers {
+            result = either(SendAsyncResolver::clear_cache(resolver).await, result);
+        }
+        result
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for resolver in &self.resolvers {
+            result = either(
+                SendAsyncResolver::reload_system_config(resolver).await,
+                result,
+            );
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::{
+        collect_many, DynResolver, HostsFile, HostsFileResolver, RecordData, ResponseCode,
+    };
+    use std::io;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Answers every query the same way and counts them
+    struct Backend {
+        answer: Result<IpAddr, fn(&DomainName) -> ResolveError>,
+        asked: AtomicUsize,
+        cache: Result<(), ()>,
+    }
+
+    impl Backend {
+        fn ok(address: [u8; 4]) -> Self {
+            Backend {
+                answer: Ok(address.into()),
+                asked: AtomicUsize::new(0),
+                cache: Ok(()),
+            }
+        }
+
+        fn failing(error: fn(&DomainName) -> ResolveError) -> Self {
+            Backend {
+                answer: Err(error),
+                asked: AtomicUsize::new(0),
+                cache: Err(()),
+            }
+        }
+
+        fn asked(&self) -> usize {
+            self.asked.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Resolver for Backend {
+        fn resolve_specific(&self, name: &DomainName, _query: QueryType) -> ResolveResult<Record> {
+            self.asked.fetch_add(1, Ordering::SeqCst);
+            match self.answer {
+                Ok(address) => Ok(Record::new(
+                    name.clone(),
+                    Duration::from_secs(60),
+                    RecordData::IpAddr(vec![address]),
+                )),
+                Err(error) => Err(error(name)),
+            }
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| Resolver::resolve_specific(self, name, query)))
+        }
+
+        fn clear_cache(&self) -> Result<(), ()> {
+            self.cache
+        }
+    }
+
+    impl AsyncResolver for Backend {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            Resolver::resolve_specific(self, name, query)
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            Resolver::resolve_many(self, name, queries)
+        }
+    }
+
+    fn io_error(_: &DomainName) -> ResolveError {
+        io::Error::new(io::ErrorKind::ConnectionRefused, "refused").into()
+    }
+
+    fn nx_domain(name: &DomainName) -> ResolveError {
+        ResolveError::NxDomain {
+            name: name.clone(),
+            query: QueryType::A,
+            ttl: None,
+        }
+    }
+
+    fn servfail(name: &DomainName) -> ResolveError {
+        ResolveError::Server {
+            rcode: ResponseCode::ServFail,
+            name: name.clone(),
+            query: QueryType::A,
+        }
+    }
+
+    fn name() -> DomainName {
+        "www.example.com".parse().unwrap()
+    }
+
+    #[test]
+    fn falls_through_on_failures_only() {
+        let resolver =
+            FallbackResolver::new(Backend::failing(io_error), Backend::ok([192, 0, 2, 1]));
+        assert_eq!(
+            Resolver::resolve(&resolver, &name()).unwrap(),
+            IpAddr::from([192, 0, 2, 1])
+        );
+        assert_eq!(
+            block_on(AsyncResolver::resolve(&resolver, &name())).unwrap(),
+            IpAddr::from([192, 0, 2, 1])
+        );
+
+        let resolver =
+            FallbackResolver::new(Backend::failing(nx_domain), Backend::ok([192, 0, 2, 1]));
+        assert!(matches!(
+            Resolver::resolve(&resolver, &name()),
+            Err(ResolveError::NxDomain { .. })
+        ));
+        assert_eq!(resolver.secondary().asked(), 0);
+    }
+
+    #[test]
+    fn chains_and_aggregates() {
+        let hosts = HostsFileResolver::new(HostsFile::parse("192.0.2.9 printer.example\n"));
+        let resolver = FallbackResolver::with_policy(
+            hosts,
+            FallbackResolver::new(Backend::failing(servfail), Backend::failing(io_error)),
+            FallbackPolicy::always(),
+        );
+        assert_eq!(
+            Resolver::resolve(&resolver, &"printer.example".parse().unwrap()).unwrap(),
+            IpAddr::from([192, 0, 2, 9])
+        );
+        assert!(matches!(
+            Resolver::resolve_specific(&resolver, &name(), QueryType::A),
+            Err(ResolveError::IO(_))
+        ));
+        assert_eq!(resolver.secondary().primary().asked(), 1);
+        // none of the children has a cache
+        assert_eq!(Resolver::clear_cache(&resolver), Err(()));
+
+        let resolver = FallbackResolver::new(Backend::failing(servfail), Backend::ok([0; 4]));
+        assert_eq!(Resolver::clear_cache(&resolver), Ok(()));
+    }
+
+    #[test]
+    fn tries_the_list_in_order() {
+        let hosts = HostsFileResolver::new(HostsFile::parse("192.0.2.9 printer.example\n"));
+        let resolvers: Vec<Box<dyn DynResolver>> = vec![
+            Box::new(hosts),
+            Box::new(Backend::failing(servfail)),
+            Box::new(Backend::ok([192, 0, 2, 1])),
+        ];
+        let resolver = FallbackList::with_policy(resolvers, FallbackPolicy::always());
+        assert_eq!(
+            Resolver::resolve(&resolver, &"printer.example".parse().unwrap()).unwrap(),
+            IpAddr::from([192, 0, 2, 9])
+        );
+        assert_eq!(
+            Resolver::resolve(&resolver, &name()).unwrap(),
+            IpAddr::from([192, 0, 2, 1])
+        );
+        // only the last backend has a cache
+        assert_eq!(Resolver::clear_cache(&resolver), Ok(()));
+
+        let resolver = FallbackList::new(vec![
+            Backend::failing(io_error),
+            Backend::failing(nx_domain),
+            Backend::ok([192, 0, 2, 1]),
+        ]);
+        assert!(matches!(
+            block_on(AsyncResolver::resolve_specific(
+                &resolver,
+                &name(),
+                QueryType::A
+            )),
+            Err(ResolveError::NxDomain { .. })
+        ));
+        assert_eq!(
+            resolver
+                .resolvers()
+                .iter()
+                .map(Backend::asked)
+                .collect::<Vec<_>>(),
+            [1, 1, 0]
+        );
+        assert_eq!(Resolver::clear_cache(&resolver), Ok(()));
+    }
+}
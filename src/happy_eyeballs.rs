@@ -0,0 +1,303 @@
+//! Dual-stack address ordering following Happy Eyeballs (RFC 8305)
+//!
+//! The helpers work on top of any [`Resolver`] or [`AsyncResolver`] so
+//! backends do not need to implement the ordering themselves.
+
+use crate::timer::Delay;
+use crate::{
+    collect_addresses, AsyncResolver, DomainName, QueryType, Record, RecordData, ResolveResult,
+    Resolver,
+};
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::net::IpAddr;
+use std::pin::{pin, Pin};
+use std::task::Poll;
+use std::time::Duration;
+
+/// The IP version of an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    V6,
+    V4,
+}
+
+impl AddressFamily {
+    pub fn of(address: &IpAddr) -> Self {
+        match address {
+            IpAddr::V6(_) => AddressFamily::V6,
+            IpAddr::V4(_) => AddressFamily::V4,
+        }
+    }
+}
+
+/// Settings of the Happy Eyeballs algorithm
+#[derive(Debug, Clone)]
+pub struct HappyEyeballs {
+    /// The family to try first, RFC 8305 recommends IPv6
+    pub preferred: AddressFamily,
+    /// How many addresses of the preferred family to try before alternating
+    pub first_address_family_count: usize,
+    /// How long to wait for the second answer once the first one arrived
+    pub resolution_delay: Duration,
+}
+
+impl Default for HappyEyeballs {
+    fn default() -> Self {
+        HappyEyeballs {
+            preferred: AddressFamily::V6,
+            first_address_family_count: 1,
+            resolution_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+impl HappyEyeballs {
+    /// Interleave the address families (RFC 8305 section 4)
+    ///
+    /// The relative order within one family is kept, so `addresses` should
+    /// already be sorted by destination address selection.
+    pub fn sort<I: IntoIterator<Item = IpAddr>>(&self, addresses: I) -> Vec<IpAddr> {
+        let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addresses
+            .into_iter()
+            .partition(|address| AddressFamily::of(address) == self.preferred);
+
+        let mut sorted = Vec::with_capacity(preferred.len() + other.len());
+        let first = self.first_address_family_count.max(1).min(preferred.len());
+        sorted.extend(preferred.drain(..first));
+
+        let mut take_other = true;
+        while !(preferred.is_empty() && other.is_empty()) {
+            let next = if take_other {
+                other.pop_front().or_else(|| preferred.pop_front())
+            } else {
+                preferred.pop_front().or_else(|| other.pop_front())
+            };
+            sorted.extend(next);
+            take_other = !take_other;
+        }
+        sorted
+    }
+
+    /// Resolve AAAA and A of `name` and return the addresses in connection order
+    pub fn resolve<R: Resolver + ?Sized>(
+        &self,
+        resolver: &R,
+        name: &DomainName,
+    ) -> ResolveResult<Vec<IpAddr>> {
+        let queries = [QueryType::AAAA, QueryType::A];
+        let records = resolver.resolve_many(name, queries.into_iter())?;
+        let addresses = collect_addresses(name, &queries, records)?;
+        Ok(self.sort(addresses.into_iter().map(|entry| entry.value)))
+    }
+
+    /// Query AAAA and A concurrently and return the addresses in connection order
+    ///
+    /// Once one family answered with addresses the other query gets at most
+    /// the resolution delay to catch up (RFC 8305 section 3). A stalled
+    /// query of either family does not hold back the addresses of the other,
+    /// and a quick AAAA answer still leaves IPv4 addresses to fall back to.
+    pub async fn resolve_async<R: AsyncResolver + ?Sized>(
+        &self,
+        resolver: &R,
+        name: &DomainName,
+    ) -> ResolveResult<Vec<IpAddr>> {
+        let mut v6 = pin!(resolver.resolve_specific(name, QueryType::AAAA));
+        let mut v4 = pin!(resolver.resolve_specific(name, QueryType::A));
+        let mut v6_result = None;
+        let mut v4_result = None;
+        let mut delay = None;
+
+        poll_fn(|cx| {
+            if v6_result.is_none() {
+                if let Poll::Ready(result) = v6.as_mut().poll(cx) {
+                    v6_result = Some(result);
+                }
+            }
+            if v4_result.is_none() {
+                if let Poll::Ready(result) = v4.as_mut().poll(cx) {
+                    v4_result = Some(result);
+                }
+            }
+            if v6_result.is_some() && v4_result.is_some() {
+                return Poll::Ready(());
+            }
+
+            let positive = |result: &Option<ResolveResult<Record>>| {
+                matches!(result, Some(Ok(Record { data: RecordData::IpAddr(addresses), .. })) if !addresses.is_empty())
+            };
+            if positive(&v6_result) || positive(&v4_result) {
+                let delay = delay.get_or_insert_with(|| Delay::new(self.resolution_delay));
+                return Pin::new(delay).poll(cx);
+            }
+            Poll::Pending
+        })
+        .await;
+
+        let mut error = None;
+        let mut records = Vec::new();
+        for result in [v6_result, v4_result].into_iter().flatten() {
+            match result {
+                Ok(record) => records.push(record),
+                Err(e) => error = Some(e),
+            }
+        }
+        match collect_addresses(name, &[QueryType::AAAA, QueryType::A], records) {
+            Ok(addresses) => Ok(self.sort(addresses.into_iter().map(|entry| entry.value))),
+            Err(e) => Err(error.unwrap_or(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResolveError;
+
+    fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+        addresses.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleaves_families() {
+        let input = ips(&[
+            "2001:db8::1",
+            "2001:db8::2",
+            "2001:db8::3",
+            "192.0.2.1",
+            "192.0.2.2",
+        ]);
+        let config = HappyEyeballs::default();
+        assert_eq!(
+            config.sort(input.clone()),
+            ips(&[
+                "2001:db8::1",
+                "192.0.2.1",
+                "2001:db8::2",
+                "192.0.2.2",
+                "2001:db8::3"
+            ])
+        );
+
+        let config = HappyEyeballs {
+            first_address_family_count: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.sort(input.clone()),
+            ips(&[
+                "2001:db8::1",
+                "2001:db8::2",
+                "192.0.2.1",
+                "2001:db8::3",
+                "192.0.2.2"
+            ])
+        );
+
+        let config = HappyEyeballs {
+            preferred: AddressFamily::V4,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.sort(input),
+            ips(&[
+                "192.0.2.1",
+                "2001:db8::1",
+                "192.0.2.2",
+                "2001:db8::2",
+                "2001:db8::3"
+            ])
+        );
+    }
+
+    /// Answers AAAA after `v6_delay` and A after `v4_delay`
+    struct Slow {
+        v6_delay: Duration,
+        v4_delay: Duration,
+    }
+
+    impl AsyncResolver for Slow {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            let address = match query {
+                QueryType::AAAA => {
+                    Delay::new(self.v6_delay).await;
+                    "2001:db8::1".parse().unwrap()
+                }
+                QueryType::A => {
+                    Delay::new(self.v4_delay).await;
+                    "192.0.2.1".parse().unwrap()
+                }
+                _ => {
+                    return Err(ResolveError::NoData {
+                        name: name.clone(),
+                        query,
+                        ttl: None,
+                    })
+                }
+            };
+            Ok(Record::new(
+                name.clone(),
+                Duration::from_secs(60),
+                RecordData::IpAddr(vec![address]),
+            ))
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            let mut records = Vec::new();
+            for query in queries {
+                records.push(self.resolve_specific(name, query).await?);
+            }
+            Ok(records)
+        }
+    }
+
+    #[test]
+    fn resolution_delay() {
+        let name = "dual.example".parse().unwrap();
+        let config = HappyEyeballs::default();
+
+        let resolver = Slow {
+            v6_delay: Duration::from_millis(10),
+            v4_delay: Duration::ZERO,
+        };
+        let addresses = crate::rt::block_on(config.resolve_async(&resolver, &name)).unwrap();
+        assert_eq!(addresses, ips(&["2001:db8::1", "192.0.2.1"]));
+
+        let resolver = Slow {
+            v6_delay: Duration::from_secs(5),
+            v4_delay: Duration::ZERO,
+        };
+        let addresses = crate::rt::block_on(config.resolve_async(&resolver, &name)).unwrap();
+        assert_eq!(addresses, ips(&["192.0.2.1"]));
+    }
+
+    #[test]
+    fn aaaa_first_waits_for_a() {
+        let name = "dual.example".parse().unwrap();
+        let config = HappyEyeballs::default();
+
+        let resolver = Slow {
+            v6_delay: Duration::ZERO,
+            v4_delay: Duration::from_millis(10),
+        };
+        let addresses = crate::rt::block_on(config.resolve_async(&resolver, &name)).unwrap();
+        assert_eq!(addresses, ips(&["2001:db8::1", "192.0.2.1"]));
+
+        let resolver = Slow {
+            v6_delay: Duration::ZERO,
+            v4_delay: Duration::from_secs(5),
+        };
+        let start = std::time::Instant::now();
+        let addresses = crate::rt::block_on(config.resolve_async(&resolver, &name)).unwrap();
+        assert_eq!(addresses, ips(&["2001:db8::1"]));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
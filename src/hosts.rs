@@ -0,0 +1,474 @@
+//! A resolver answering from a hosts file like `/etc/hosts`
+//!
+//! Every line holds an address followed by the canonical name and any
+//! number of aliases, `#` starts a comment. Lines that can not be parsed
+//! are skipped like the C library does.
+//!
+//! Scoped IPv6 addresses like `fe80::1%eth0` keep their zone as scope id,
+//! either given as number or as the name of an interface. Names are looked
+//! up in `/sys/class/net`, so only Linux knows them, and lines with a zone
+//! that names no interface are skipped like an unknown interface makes
+//! `getaddrinfo` fail.
+
+use crate::{
+    collect_many, AsyncResolver, DomainName, QueryType, Record, RecordData, RecordSource,
+    ResolveError, ResolveResult, Resolver, SendAsyncResolver,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The addresses of one name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostEntry {
+    /// The first name of the line the name was found on first
+    pub canonical: DomainName,
+    /// All addresses in file order
+    pub addresses: Vec<IpAddr>,
+    /// The scope id of each address of `addresses`, `None` without zone
+    pub scope_ids: Vec<Option<u32>>,
+}
+
+impl HostEntry {
+    /// The addresses with `port` and the scope id of their zone, ready to
+    /// connect to
+    pub fn socket_addrs(&self, port: u16) -> Vec<SocketAddr> {
+        self.addresses
+            .iter()
+            .zip(&self.scope_ids)
+            .map(|(address, scope_id)| match (address, scope_id) {
+                (IpAddr::V6(v6), Some(scope_id)) => {
+                    SocketAddrV6::new(*v6, port, 0, *scope_id).into()
+                }
+                _ => SocketAddr::new(*address, port),
+            })
+            .collect()
+    }
+}
+
+/// An address with an optional IPv6 zone, see the module documentation
+fn parse_address(text: &str) -> Option<(IpAddr, Option<u32>)> {
+    let (address, zone) = match text.split_once('%') {
+        Some((address, zone)) => (address, Some(zone)),
+        None => (text, None),
+    };
+    let address: IpAddr = address.parse().ok()?;
+    match zone {
+        None => Some((address, None)),
+        Some(_) if address.is_ipv4() => None,
+        Some(zone) => Some((address, Some(scope_id(zone)?))),
+    }
+}
+
+/// The index of the interface `zone` names
+fn scope_id(zone: &str) -> Option<u32> {
+    if let Ok(index) = zone.parse() {
+        return Some(index);
+    }
+    // keeps the lookup inside /sys/class/net
+    if zone.is_empty() || zone.contains(['/', '.']) {
+        return None;
+    }
+    let index = fs::read_to_string(Path::new("/sys/class/net").join(zone).join("ifindex")).ok()?;
+    index.trim().parse().ok()
+}
+
+/// The parsed content of a hosts file
+///
+/// Names are treated as fully qualified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostsFile {
+    by_name: HashMap<DomainName, HostEntry>,
+    /// Keyed by the reverse pointer name of the address
+    by_address: HashMap<DomainName, Vec<DomainName>>,
+}
+
+impl HostsFile {
+    /// Where the system hosts file lives
+    pub const PATH: &'static str = "/etc/hosts";
+
+    pub fn parse(text: &str) -> Self {
+        let mut hosts = HostsFile::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let (address, scope_id) = match words.next().and_then(parse_address) {
+                Some(address) => address,
+                None => continue,
+            };
+            let names: Vec<DomainName> = words
+                .filter_map(|name| DomainName::new(name).ok())
+                .map(|name| name.to_fqdn())
+                .collect();
+            let canonical = match names.first() {
+                Some(canonical) => canonical.clone(),
+                None => continue,
+            };
+
+            for name in &names {
+                let entry = hosts
+                    .by_name
+                    .entry(name.clone())
+                    .or_insert_with(|| HostEntry {
+                        canonical: canonical.clone(),
+                        addresses: Vec::new(),
+                        scope_ids: Vec::new(),
+                    });
+                if !entry.addresses.contains(&address) {
+                    entry.addresses.push(address);
+                    entry.scope_ids.push(scope_id);
+                }
+            }
+            let reverse = hosts
+                .by_address
+                .entry(DomainName::reverse_pointer(&address))
+                .or_default();
+            for name in names {
+                if !reverse.contains(&name) {
+                    reverse.push(name);
+                }
+            }
+        }
+        hosts
+    }
+
+    /// Read and parse the file at `path`, a missing file has no entries
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(HostsFile::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HostsFile::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn lookup(&self, name: &DomainName) -> Option<&HostEntry> {
+        self.by_name.get(&name.to_fqdn())
+    }
+
+    /// The names of `address`, canonical names before aliases
+    pub fn names(&self, address: &IpAddr) -> &[DomainName] {
+        self.by_address
+            .get(&DomainName::reverse_pointer(address))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn answer(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        let (canonical, data) = match (query, self.lookup(name)) {
+            (QueryType::A | QueryType::AAAA, Some(entry)) => {
+                let addresses: Vec<_> = entry
+                    .addresses
+                    .iter()
+                    .filter(|address| match query {
+                        QueryType::A => address.is_ipv4(),
+                        _ => address.is_ipv6(),
+                    })
+                    .copied()
+                    .collect();
+                if addresses.is_empty() {
+                    return Err(no_data(name, query));
+                }
+                (entry.canonical.clone(), RecordData::IpAddr(addresses))
+            }
+            (QueryType::PTR, _) => match self.by_address.get(&name.to_fqdn()) {
+                Some(names) => (name.clone(), RecordData::PTR(names.clone())),
+                None => return Err(nx_domain(name, query)),
+            },
+            (_, Some(_)) => return Err(no_data(name, query)),
+            (_, None) => return Err(nx_domain(name, query)),
+        };
+
+        let mut record = Record::new(name.clone(), Duration::ZERO, data);
+        record.canonical_name = canonical;
+        record.source = RecordSource::HostsFile;
+        Ok(record)
+    }
+}
+
+fn no_data(name: &DomainName, query: QueryType) -> ResolveError {
+    ResolveError::NoData {
+        name: name.clone(),
+        query,
+        ttl: None,
+    }
+}
+
+fn nx_domain(name: &DomainName, query: QueryType) -> ResolveError {
+    ResolveError::NxDomain {
+        name: name.clone(),
+        query,
+        ttl: None,
+    }
+}
+
+#[derive(Debug)]
+enum Source {
+    Fixed(Arc<HostsFile>),
+    /// The file and its content once parsed
+    File(PathBuf, Mutex<Option<Arc<HostsFile>>>),
+}
+
+/// A resolver that only knows the entries of a hosts file
+///
+/// Only A, AAAA and PTR queries are answered. Names that are not in the
+/// file fail with NXDOMAIN, other record types of known names with NODATA.
+/// The records have a TTL of zero because the file may change any time.
+/// They can not carry the zone of scoped addresses, which
+/// [`HostEntry::socket_addrs`] of [`HostsFileResolver::hosts`] adds.
+#[derive(Debug)]
+pub struct HostsFileResolver {
+    source: Source,
+}
+
+impl HostsFileResolver {
+    /// Answer from fixed entries, handy as a fixture in tests
+    pub fn new(hosts: HostsFile) -> Self {
+        HostsFileResolver {
+            source: Source::Fixed(Arc::new(hosts)),
+        }
+    }
+
+    /// Answer from the file at `path`
+    ///
+    /// `reload_system_config` reads the file again, `clear_cache` drops the
+    /// parsed entries until the next lookup.
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let hosts = HostsFile::from_file(&path)?;
+        Ok(HostsFileResolver {
+            source: Source::File(path, Mutex::new(Some(Arc::new(hosts)))),
+        })
+    }
+
+    /// Answer from [`HostsFile::PATH`]
+    pub fn system() -> io::Result<Self> {
+        HostsFileResolver::open(HostsFile::PATH)
+    }
+
+    /// The current entries, parsing the file if they were dropped
+    pub fn hosts(&self) -> io::Result<Arc<HostsFile>> {
+        match &self.source {
+            Source::Fixed(hosts) => Ok(hosts.clone()),
+            Source::File(path, parsed) => {
+                let mut parsed = parsed.lock().unwrap();
+                if let Some(hosts) = &*parsed {
+                    return Ok(hosts.clone());
+                }
+                let hosts = Arc::new(HostsFile::from_file(path)?);
+                *parsed = Some(hosts.clone());
+                Ok(hosts)
+            }
+        }
+    }
+
+    fn lookup(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.hosts()?.answer(name, query)
+    }
+
+    fn clear(&self) -> Result<(), ()> {
+        match &self.source {
+            Source::Fixed(_) => Err(()),
+            Source::File(_, parsed) => {
+                *parsed.lock().unwrap() = None;
+                Ok(())
+            }
+        }
+    }
+
+    fn reload(&self) -> Result<(), ()> {
+        match &self.source {
+            Source::Fixed(_) => Err(()),
+            Source::File(path, parsed) => {
+                let hosts = HostsFile::from_file(path).map_err(|_| ())?;
+                *parsed.lock().unwrap() = Some(Arc::new(hosts));
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Resolver for HostsFileResolver {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.lookup(name, query)
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        collect_many(queries.map(|query| self.lookup(name, query)))
+    }
+
+    fn clear_cache(&self) -> Result<(), ()> {
+        self.clear()
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        self.reload()
+    }
+}
+
+impl AsyncResolver for HostsFileResolver {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.lookup(name, query)
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        collect_many(queries.map(|query| self.lookup(name, query)))
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.clear()
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        self.reload()
+    }
+}
+
+impl SendAsyncResolver for HostsFileResolver {
+    crate::forward_async_resolver!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTS: &str = "\
+# static entries
+127.0.0.1	localhost
+::1		localhost ip6-localhost ip6-loopback
+192.0.2.10	db.corp.example db   # primary
+192.0.2.11	db.corp.example
+fe80::10%2	db.corp.example
+fe80::20%lo	router.corp.example
+fe80::30%no-such-if0	switch.corp.example
+2001:db8::5	printer.corp.example
+not-an-address	broken.example
+192.0.2.99
+";
+
+    fn name(name: &str) -> DomainName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_hosts_syntax() {
+        let hosts = HostsFile::parse(HOSTS);
+        let db = hosts.lookup(&name("db.corp.example.")).unwrap();
+        assert_eq!(
+            db.addresses,
+            [
+                "192.0.2.10".parse::<IpAddr>().unwrap(),
+                "192.0.2.11".parse().unwrap(),
+                "fe80::10".parse().unwrap(),
+            ]
+        );
+        let alias = hosts.lookup(&name("DB")).unwrap();
+        assert_eq!(alias.canonical, name("db.corp.example."));
+        assert_eq!(alias.addresses.len(), 1);
+        assert_eq!(
+            hosts.names(&"::1".parse().unwrap()),
+            [
+                name("localhost."),
+                name("ip6-localhost."),
+                name("ip6-loopback."),
+            ]
+        );
+        assert!(hosts.lookup(&name("broken.example")).is_none());
+    }
+
+    #[test]
+    fn scoped_addresses() {
+        let hosts = HostsFile::parse(HOSTS);
+        let db = hosts.lookup(&name("db.corp.example")).unwrap();
+        assert_eq!(db.scope_ids, [None, None, Some(2)]);
+        assert_eq!(
+            db.socket_addrs(80)[2],
+            "[fe80::10%2]:80".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            hosts.names(&"fe80::10".parse().unwrap()),
+            [name("db.corp.example.")]
+        );
+
+        let resolver = HostsFileResolver::new(hosts.clone());
+        let record =
+            Resolver::resolve_specific(&resolver, &name("db.corp.example"), QueryType::AAAA)
+                .unwrap();
+        assert_eq!(
+            record.data,
+            RecordData::IpAddr(vec!["fe80::10".parse().unwrap()])
+        );
+
+        if Path::new("/sys/class/net/lo/ifindex").exists() {
+            let router = hosts.lookup(&name("router.corp.example")).unwrap();
+            assert_eq!(router.scope_ids, [Some(1)]);
+        }
+        assert!(hosts.lookup(&name("switch.corp.example")).is_none());
+        assert!(HostsFile::parse("192.0.2.1%2 zoned.example\n")
+            .lookup(&name("zoned.example"))
+            .is_none());
+    }
+
+    #[test]
+    fn answers_queries() {
+        let resolver = HostsFileResolver::new(HostsFile::parse(HOSTS));
+        let record = Resolver::resolve_specific(&resolver, &name("db"), QueryType::A).unwrap();
+        assert_eq!(record.source, RecordSource::HostsFile);
+        assert_eq!(record.canonical_name, name("db.corp.example."));
+        let all = Resolver::resolve_all(&resolver, &name("db.corp.example")).unwrap();
+        assert_eq!(all.len(), 3);
+
+        assert!(matches!(
+            Resolver::resolve_specific(&resolver, &name("printer.corp.example"), QueryType::A),
+            Err(ResolveError::NoData { .. })
+        ));
+        assert!(matches!(
+            Resolver::resolve_specific(&resolver, &name("web.corp.example"), QueryType::A),
+            Err(ResolveError::NxDomain { .. })
+        ));
+        assert_eq!(
+            Resolver::reverse(&resolver, "192.0.2.10".parse().unwrap()).unwrap(),
+            [name("db.corp.example."), name("db.")]
+        );
+        assert_eq!(Resolver::clear_cache(&resolver), Err(()));
+    }
+
+    #[test]
+    fn reload_and_clear() {
+        let path =
+            std::env::temp_dir().join(format!("resolver_types-{}-hosts", std::process::id()));
+        fs::write(&path, "192.0.2.1 app.example\n").unwrap();
+        let resolver = HostsFileResolver::open(&path).unwrap();
+        let app = name("app.example");
+
+        fs::write(&path, "192.0.2.2 app.example\n").unwrap();
+        assert_eq!(
+            Resolver::resolve(&resolver, &app).unwrap(),
+            IpAddr::from([192, 0, 2, 1])
+        );
+        assert_eq!(Resolver::reload_system_config(&resolver), Ok(()));
+        assert_eq!(
+            Resolver::resolve(&resolver, &app).unwrap(),
+            IpAddr::from([192, 0, 2, 2])
+        );
+
+        fs::write(&path, "192.0.2.3 app.example\n").unwrap();
+        assert_eq!(Resolver::clear_cache(&resolver), Ok(()));
+        assert_eq!(
+            Resolver::resolve(&resolver, &app).unwrap(),
+            IpAddr::from([192, 0, 2, 3])
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+//! Bridges to the resolvers of hyper 0.14
+//!
+//! hyper resolves names with any `tower_service::Service<Name>` that yields
+//! socket addresses. [`ResolverService`] makes such a service of an
+//! [`AsyncResolver`] for `HttpConnector::new_with_resolver`, while
+//! [`HyperResolver`] goes the other way and turns a hyper resolver like
+//! `GaiResolver` into an [`AsyncResolver`].
+
+use crate::{
+    collect_many, AsyncResolver, BoxFuture, DomainName, QueryType, Record, RecordData,
+    ResolveError, ResolveResult, SendAsyncResolver,
+};
+use hyper::client::connect::dns::{GaiResolver, Name};
+use std::error::Error;
+use std::future::poll_fn;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower_service::Service;
+
+/// A [`SendAsyncResolver`] as resolver of hyper's `HttpConnector`
+///
+/// Every name is looked up with `resolve_all`, the port of the returned
+/// addresses is zero as hyper fills in the port of the URI.
+pub struct ResolverService<R> {
+    inner: Arc<R>,
+}
+
+impl<R> ResolverService<R> {
+    pub fn new(inner: R) -> Self {
+        ResolverService {
+            inner: Arc::new(inner),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R> Clone for ResolverService<R> {
+    fn clone(&self) -> Self {
+        ResolverService {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<R: SendAsyncResolver + 'static> Service<Name> for ResolverService<R> {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = ResolveError;
+    type Future = BoxFuture<'static, Result<Self::Response, ResolveError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ResolveError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let name: DomainName = name
+                .as_str()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let addresses = SendAsyncResolver::resolve_all(&*inner, &name).await?;
+            let addresses: Vec<_> = addresses
+                .into_iter()
+                .map(|entry| SocketAddr::new(entry.value, 0))
+                .collect();
+            Ok(addresses.into_iter())
+        })
+    }
+}
+
+/// A hyper resolver as [`AsyncResolver`]
+///
+/// hyper resolvers only know addresses, so only A and AAAA queries are
+/// answered and both are served by a single lookup in `resolve_many`. There
+/// is no TTL either, the records expire right away. Errors of the service
+/// are I/O errors, a generic service has no way to tell a name that does
+/// not exist from a failure.
+#[derive(Debug, Clone)]
+pub struct HyperResolver<S> {
+    service: S,
+    /// Tells the errors of names that do not exist, see [`HyperResolver::gai`]
+    no_such_host: fn(&io::Error) -> bool,
+}
+
+impl<S> HyperResolver<S> {
+    pub fn new(service: S) -> Self {
+        HyperResolver {
+            service,
+            no_such_host: |_| false,
+        }
+    }
+
+    pub fn service(&self) -> &S {
+        &self.service
+    }
+}
+
+impl HyperResolver<GaiResolver> {
+    /// Resolve with `getaddrinfo`, which needs a running tokio runtime
+    ///
+    /// Names `getaddrinfo` does not know are reported as NXDOMAIN. Only the
+    /// message of its `EAI_NONAME` error is left at that point, so this
+    /// works as long as the messages are English. With another locale
+    /// these names fail with an I/O error.
+    pub fn gai() -> Self {
+        HyperResolver {
+            service: GaiResolver::new(),
+            no_such_host: is_no_such_host,
+        }
+    }
+}
+
+impl<S> HyperResolver<S>
+where
+    S: Service<Name> + Clone + Send + Sync,
+    S::Response: Iterator<Item = SocketAddr>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send,
+{
+    async fn lookup(&self, name: &DomainName) -> ResolveResult<Vec<IpAddr>> {
+        let host: Name = name
+            .as_str()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut service = self.service.clone();
+        poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .map_err(into_io)?;
+        let addresses = service.call(host).await.map_err(into_io)?;
+        Ok(addresses.map(|address| address.ip()).collect())
+    }
+}
+
+/// Keep the kind of I/O errors, which is what `GaiResolver` returns
+fn into_io<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> io::Error {
+    match error.into().downcast::<io::Error>() {
+        Ok(error) => *error,
+        Err(error) => io::Error::other(error),
+    }
+}
+
+/// English messages of `getaddrinfo` for names that do not exist, which is
+/// all that is left of `EAI_NONAME` once `GaiResolver` turned it into an
+/// I/O error
+const NO_SUCH_HOST: [&str; 3] = [
+    // glibc and musl
+    "Name or service not known",
+    // BSD and macOS
+    "nodename nor servname provided",
+    // Windows, WSAHOST_NOT_FOUND
+    "No such host is known",
+];
+
+fn is_no_such_host(error: &io::Error) -> bool {
+    let message = error.to_string();
+    NO_SUCH_HOST.iter().any(|known| message.contains(known))
+}
+
+fn is_address(query: QueryType) -> bool {
+    matches!(query, QueryType::A | QueryType::AAAA)
+}
+
+fn unsupported(query: QueryType) -> ResolveError {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("hyper resolvers can not answer {:?} queries", query),
+    )
+    .into()
+}
+
+/// The addresses of `query`'s family out of one lookup
+fn answer(
+    name: &DomainName,
+    query: QueryType,
+    addresses: &ResolveResult<Vec<IpAddr>>,
+    no_such_host: fn(&io::Error) -> bool,
+) -> ResolveResult<Record> {
+    let addresses: Vec<_> = match addresses {
+        Ok(addresses) => addresses
+            .iter()
+            .copied()
+            .filter(|address| address.is_ipv6() == (query == QueryType::AAAA))
+            .collect(),
+        Err(ResolveError::IO(e)) if no_such_host(e) => {
+            return Err(ResolveError::NxDomain {
+                name: name.clone(),
+                query,
+                ttl: None,
+            })
+        }
+        Err(e) => return Err(e.clone()),
+    };
+    if addresses.is_empty() {
+        return Err(ResolveError::NoData {
+            name: name.clone(),
+            query,
+            ttl: None,
+        });
+    }
+    Ok(Record::new(
+        name.clone(),
+        Duration::ZERO,
+        RecordData::IpAddr(addresses),
+    ))
+}
+
+impl<S> AsyncResolver for HyperResolver<S>
+where
+    S: Service<Name> + Clone + Send + Sync,
+    S::Response: Iterator<Item = SocketAddr>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send,
+{
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        if !is_address(query) {
+            return Err(unsupported(query));
+        }
+        answer(name, query, &self.lookup(name).await, self.no_such_host)
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let addresses = if queries.iter().any(|query| is_address(*query)) {
+            self.lookup(name).await
+        } else {
+            Ok(Vec::new())
+        };
+        collect_many(queries.into_iter().map(|query| {
+            if is_address(query) {
+                answer(name, query, &addresses, self.no_such_host)
+            } else {
+                Err(unsupported(query))
+            }
+        }))
+    }
+}
+
+impl<S> SendAsyncResolver for HyperResolver<S>
+where
+    S: Service<Name> + Clone + Send + Sync,
+    S::Response: Iterator<Item = SocketAddr>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send,
+{
+    crate::forward_async_resolver!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::{AsyncFromSync, HostsFile, HostsFileResolver};
+    use hyper::client::connect::HttpConnector;
+    use std::future::{ready, Ready};
+
+    /// Knows one dual-stack host, fails like getaddrinfo for two more and
+    /// does not find anything else
+    #[derive(Clone)]
+    struct MockGai;
+
+    impl Service<Name> for MockGai {
+        type Response = std::vec::IntoIter<SocketAddr>;
+        type Error = io::Error;
+        type Future = Ready<io::Result<Self::Response>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, name: Name) -> Self::Future {
+            ready(match name.as_str() {
+                "www.example.com" => Ok(vec![
+                    "[2001:db8::1]:0".parse().unwrap(),
+                    "192.0.2.1:0".parse().unwrap(),
+                ]
+                .into_iter()),
+                "gai.example" => Err(io::Error::other(
+                    "failed to lookup address information: Name or service not known",
+                )),
+                "down.example" => Err(io::Error::other(
+                    "failed to lookup address information: Temporary failure in name resolution",
+                )),
+                _ => Err(io::Error::new(io::ErrorKind::NotFound, "unknown host")),
+            })
+        }
+    }
+
+    fn hosts_service() -> ResolverService<AsyncFromSync<HostsFileResolver>> {
+        let hosts = HostsFile::parse("192.0.2.9 printer.example\n2001:db8::9 printer.example\n");
+        ResolverService::new(AsyncFromSync::new(HostsFileResolver::new(hosts)))
+    }
+
+    #[test]
+    fn serves_hyper() {
+        fn connector<C: Service<hyper::Uri>>(_: &C) {}
+        connector(&HttpConnector::new_with_resolver(hosts_service()));
+
+        let mut service = hosts_service();
+        let addresses: Vec<_> = block_on(service.call("printer.example".parse().unwrap()))
+            .unwrap()
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                "[2001:db8::9]:0".parse::<SocketAddr>().unwrap(),
+                "192.0.2.9:0".parse().unwrap()
+            ]
+        );
+        assert!(matches!(
+            block_on(service.call("unknown.example".parse().unwrap())),
+            Err(ResolveError::NxDomain { .. })
+        ));
+    }
+
+    #[test]
+    fn gai_reports_unknown_names() {
+        let resolver = HyperResolver {
+            service: MockGai,
+            no_such_host: is_no_such_host,
+        };
+        match block_on(AsyncResolver::resolve(
+            &resolver,
+            &"gai.example".parse().unwrap(),
+        )) {
+            Err(ResolveError::NxDomain { name, query, .. }) => {
+                assert_eq!(name.as_str(), "gai.example");
+                assert_eq!(query, QueryType::AAAA);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // only the message of EAI_NONAME counts, not any NotFound error
+        for failing in ["other.example", "down.example"] {
+            assert!(matches!(
+                block_on(AsyncResolver::resolve(&resolver, &failing.parse().unwrap())),
+                Err(ResolveError::IO(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn resolves_with_hyper() {
+        let resolver = HyperResolver::new(MockGai);
+        let name: DomainName = "www.example.com".parse().unwrap();
+        let addresses: Vec<_> = block_on(AsyncResolver::resolve_all(&resolver, &name))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.value)
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+                "192.0.2.1".parse().unwrap()
+            ]
+        );
+        let record = block_on(AsyncResolver::resolve_specific(
+            &resolver,
+            &name,
+            QueryType::A,
+        ))
+        .unwrap();
+        assert_eq!(record.ttl, Duration::ZERO);
+
+        // a generic service can not tell missing names from failures
+        for missing in ["other.example", "gai.example", "down.example"] {
+            assert!(matches!(
+                block_on(AsyncResolver::resolve(&resolver, &missing.parse().unwrap())),
+                Err(ResolveError::IO(_))
+            ));
+        }
+        match block_on(AsyncResolver::resolve_specific(
+            &resolver,
+            &name,
+            QueryType::MX,
+        )) {
+            Err(ResolveError::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
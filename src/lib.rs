@@ -2,127 +2,595 @@
 //! `trust-dns-resolver` or `GaiResolver` from hyper
 //!
 
-use async_trait::async_trait;
+use std::future::Future;
 use std::iter::Iterator;
 use std::net::IpAddr;
 
-pub type ResolveResult<T> = Result<T, ResolveError>;
+pub mod adapter;
+pub mod address_selection;
+pub mod cache;
+pub mod coalesce;
+pub mod config;
+pub mod dynamic;
+mod error;
+pub mod fallback;
+pub mod happy_eyeballs;
+pub mod hosts;
+#[cfg(feature = "hyper")]
+pub mod hyper_dns;
+pub mod mail;
+mod name;
+pub mod race;
+mod random;
+pub mod record;
+pub mod reverse;
+mod rt;
+pub mod search;
+pub mod service;
+#[cfg(feature = "stub")]
+pub mod stub;
+mod timer;
+pub mod txt;
+pub mod wire;
+
+pub use adapter::{AsyncFromSync, BlockingResolver};
+pub use address_selection::{AddressSelection, PolicyTable};
+pub use cache::{CacheConfig, CachingResolver};
+pub use coalesce::CoalescingResolver;
+pub use config::{ResolverConfig, SystemConfig};
+pub use dynamic::{BoxFuture, DynAsyncResolver, DynResolver};
+pub use error::{ResolveError, ResolveResult, ResponseCode};
+pub use fallback::{FallbackList, FallbackPolicy, FallbackResolver};
+pub use happy_eyeballs::{AddressFamily, HappyEyeballs};
+pub use hosts::{HostsFile, HostsFileResolver};
+#[cfg(feature = "hyper")]
+pub use hyper_dns::{HyperResolver, ResolverService};
+pub use name::{DomainName, NameError, MAX_LABEL_LEN, MAX_NAME_LEN};
+pub use race::{RaceConfig, RaceResolver};
+pub use record::{
+    CaaRecord, DnskeyRecord, DsRecord, MxRecord, NaptrRecord, PriorityEntry, QueryType, Record,
+    RecordClass, RecordData, RecordSource, SoaRecord, SrvRecord, SvcbRecord, TlsaRecord, TtlEntry,
+    TxtRecord, UnknownRecord,
+};
+pub use reverse::{forward_confirmed, forward_confirmed_async};
+pub use search::{SearchConfig, SearchResolver};
+pub use service::{service_name, SrvTargets};
+#[cfg(feature = "stub")]
+pub use stub::StubResolver;
 
 /// The simplified interface that all resolvers share
-#[async_trait]
+///
+/// Implementations can use `async fn` for the methods. The futures are
+/// returned unboxed and need not be `Send`, so a resolver built on a
+/// single threaded runtime works with every wrapper that does not move
+/// lookups between threads. [`SendAsyncResolver`] is the variant for those
+/// that do and [`DynAsyncResolver`] the boxed form for trait objects.
 pub trait AsyncResolver {
-    /// Resolve IPv6 and IPv4
-    async fn resolve(&self) -> ResolveResult<IpAddr> {
-        let queries = [QueryType::AAAA, QueryType::A];
-        let records = self.resolve_many(queries.into_iter()).await?;
-        // try get first element
-        match records
-            .into_iter()
-            .filter_map(|record| match record {
-                Record::IpAddr(ip) => Some(ip),
-                _ => None,
-            })
-            .next()
-        {
-            Some(ip) => Ok(ip),
-            None => Err(ResolveError::NotResolved),
+    /// Resolve IPv6 and IPv4 of `name`
+    fn resolve(&self, name: &DomainName) -> impl Future<Output = ResolveResult<IpAddr>> {
+        async move {
+            // try get first element
+            let addresses = self.resolve_all(name).await?;
+            Ok(addresses[0].value)
+        }
+    }
+
+    /// Resolve every IPv6 and IPv4 address of `name`
+    fn resolve_all(
+        &self,
+        name: &DomainName,
+    ) -> impl Future<Output = ResolveResult<Vec<TtlEntry<IpAddr>>>> {
+        async move {
+            let queries = [QueryType::AAAA, QueryType::A];
+            let records = self.resolve_many(name, queries.into_iter()).await?;
+            collect_addresses(name, &queries, records)
+        }
+    }
+
+    /// Resolve the names of `address` with a PTR query
+    fn reverse(&self, address: IpAddr) -> impl Future<Output = ResolveResult<Vec<DomainName>>> {
+        async move {
+            let name = DomainName::reverse_pointer(&address);
+            let record = self.resolve_specific(&name, QueryType::PTR).await?;
+            ptr_names(record)
         }
     }
 
-    async fn resolve_specific(&self, query: QueryType) -> ResolveResult<Record>;
-    async fn resolve_many<I: Iterator<Item = QueryType>>(
+    /// Resolve a single record type of `name`
+    fn resolve_specific(
         &self,
+        name: &DomainName,
+        query: QueryType,
+    ) -> impl Future<Output = ResolveResult<Record>>;
+    /// Resolve several record types of `name` at once
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
         queries: I,
-    ) -> ResolveResult<Vec<Record>>;
+    ) -> impl Future<Output = ResolveResult<Vec<Record>>>;
 
     /// Potentially clear the cache of the actual implementation
     ///
     /// Returns Ok if implemented
-    async fn clear_cache(&self) -> Result<(), ()> {
-        Err(())
+    fn clear_cache(&self) -> impl Future<Output = Result<(), ()>> {
+        async { Err(()) }
+    }
+    /// The system configuration this resolver was built from, if any
+    fn system_config(&self) -> Option<&SystemConfig> {
+        None
     }
     /// If the system settings are cached reload them
     ///
-    /// Returns ok if implemented
-    async fn reload_system_config(&self) -> Result<(), ()> {
-        Err(())
+    /// Returns ok if implemented, by default the file of `system_config`
+    /// is parsed again if it changed.
+    fn reload_system_config(&self) -> impl Future<Output = Result<(), ()>> {
+        async move {
+            match self.system_config() {
+                Some(config) => config.reload().map(|_| ()).map_err(|_| ()),
+                None => Err(()),
+            }
+        }
     }
 }
 
+/// An [`AsyncResolver`] whose futures are `Send`
+///
+/// Generic code can not require the futures of [`AsyncResolver`] to be
+/// `Send`, so the methods are repeated here for the places that move
+/// lookups to other threads: [`DynAsyncResolver`], [`BlockingResolver`]
+/// and hyper's connector. Resolvers of concrete types implement it with
+/// [`forward_async_resolver!`], which forwards every method to their
+/// [`AsyncResolver`] implementation, whose futures the compiler knows to be
+/// `Send`. That way the two traits can not answer differently:
+///
+/// ```ignore
+/// impl SendAsyncResolver for MyResolver {
+///     resolver_types::forward_async_resolver!();
+/// }
+/// ```
+///
+/// Wrappers generic over the inner resolver can not forward like that and
+/// implement the methods they override in both traits. Both traits name
+/// their methods alike, calls have to be qualified where both are in scope.
+pub trait SendAsyncResolver: AsyncResolver + Send + Sync {
+    /// Resolve IPv6 and IPv4 of `name`
+    fn resolve(&self, name: &DomainName) -> impl Future<Output = ResolveResult<IpAddr>> + Send {
+        async move {
+            let addresses = SendAsyncResolver::resolve_all(self, name).await?;
+            Ok(addresses[0].value)
+        }
+    }
+
+    /// Resolve every IPv6 and IPv4 address of `name`
+    fn resolve_all(
+        &self,
+        name: &DomainName,
+    ) -> impl Future<Output = ResolveResult<Vec<TtlEntry<IpAddr>>>> + Send {
+        async move {
+            let queries = [QueryType::AAAA, QueryType::A];
+            let records = SendAsyncResolver::resolve_many(self, name, queries.into_iter()).await?;
+            collect_addresses(name, &queries, records)
+        }
+    }
+
+    /// Resolve the names of `address` with a PTR query
+    fn reverse(
+        &self,
+        address: IpAddr,
+    ) -> impl Future<Output = ResolveResult<Vec<DomainName>>> + Send {
+        async move {
+            let name = DomainName::reverse_pointer(&address);
+            let record = SendAsyncResolver::resolve_specific(self, &name, QueryType::PTR).await?;
+            ptr_names(record)
+        }
+    }
+
+    /// Resolve a single record type of `name`
+    fn resolve_specific(
+        &self,
+        name: &DomainName,
+        query: QueryType,
+    ) -> impl Future<Output = ResolveResult<Record>> + Send;
+    /// Resolve several record types of `name` at once
+    fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> impl Future<Output = ResolveResult<Vec<Record>>> + Send;
+
+    /// Potentially clear the cache of the actual implementation
+    ///
+    /// Returns Ok if implemented
+    fn clear_cache(&self) -> impl Future<Output = Result<(), ()>> + Send {
+        async { Err(()) }
+    }
+    /// If the system settings are cached reload them
+    ///
+    /// Returns ok if implemented, by default the file of `system_config`
+    /// is parsed again if it changed.
+    fn reload_system_config(&self) -> impl Future<Output = Result<(), ()>> + Send {
+        async move {
+            match self.system_config() {
+                Some(config) => config.reload().map(|_| ()).map_err(|_| ()),
+                None => Err(()),
+            }
+        }
+    }
+}
+
+/// The body of a [`SendAsyncResolver`] implementation forwarding every
+/// method to the [`AsyncResolver`] implementation of the same type
+#[macro_export]
+macro_rules! forward_async_resolver {
+    () => {
+        fn resolve(
+            &self,
+            name: &$crate::DomainName,
+        ) -> impl ::std::future::Future<Output = $crate::ResolveResult<::std::net::IpAddr>>
+               + ::std::marker::Send {
+            $crate::AsyncResolver::resolve(self, name)
+        }
+
+        fn resolve_all(
+            &self,
+            name: &$crate::DomainName,
+        ) -> impl ::std::future::Future<
+            Output = $crate::ResolveResult<::std::vec::Vec<$crate::TtlEntry<::std::net::IpAddr>>>,
+        > + ::std::marker::Send {
+            $crate::AsyncResolver::resolve_all(self, name)
+        }
+
+        fn reverse(
+            &self,
+            address: ::std::net::IpAddr,
+        ) -> impl ::std::future::Future<
+            Output = $crate::ResolveResult<::std::vec::Vec<$crate::DomainName>>,
+        > + ::std::marker::Send {
+            $crate::AsyncResolver::reverse(self, address)
+        }
+
+        fn resolve_specific(
+            &self,
+            name: &$crate::DomainName,
+            query: $crate::QueryType,
+        ) -> impl ::std::future::Future<Output = $crate::ResolveResult<$crate::Record>>
+               + ::std::marker::Send {
+            $crate::AsyncResolver::resolve_specific(self, name, query)
+        }
+
+        fn resolve_many<I: ::std::iter::Iterator<Item = $crate::QueryType> + ::std::marker::Send>(
+            &self,
+            name: &$crate::DomainName,
+            queries: I,
+        ) -> impl ::std::future::Future<
+            Output = $crate::ResolveResult<::std::vec::Vec<$crate::Record>>,
+        > + ::std::marker::Send {
+            $crate::AsyncResolver::resolve_many(self, name, queries)
+        }
+
+        fn clear_cache(
+            &self,
+        ) -> impl ::std::future::Future<Output = ::std::result::Result<(), ()>>
+               + ::std::marker::Send {
+            $crate::AsyncResolver::clear_cache(self)
+        }
+
+        fn reload_system_config(
+            &self,
+        ) -> impl ::std::future::Future<Output = ::std::result::Result<(), ()>>
+               + ::std::marker::Send {
+            $crate::AsyncResolver::reload_system_config(self)
+        }
+    };
+}
+
 /// The simplified interface that all resolvers share
 pub trait Resolver {
-    /// Resolve IPv6 and IPv4
-    fn resolve(&self) -> ResolveResult<IpAddr> {
-        let queries = [QueryType::AAAA, QueryType::A];
-        let records = self.resolve_many(queries.into_iter())?;
+    /// Resolve IPv6 and IPv4 of `name`
+    fn resolve(&self, name: &DomainName) -> ResolveResult<IpAddr> {
         // try get first element
-        match records
-            .into_iter()
-            .filter_map(|record| match record {
-                Record::IpAddr(ip) => Some(ip),
-                _ => None,
-            })
-            .next()
-        {
-            Some(ip) => Ok(ip),
-            None => Err(ResolveError::NotResolved),
-        }
+        let addresses = self.resolve_all(name)?;
+        Ok(addresses[0].value)
     }
 
-    fn resolve_specific(&self, query: QueryType) -> ResolveResult<Record>;
-    fn resolve_many<I: Iterator<Item = QueryType>>(&self, queries: I)
-        -> ResolveResult<Vec<Record>>;
+    /// Resolve every IPv6 and IPv4 address of `name`
+    fn resolve_all(&self, name: &DomainName) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+        let queries = [QueryType::AAAA, QueryType::A];
+        let records = self.resolve_many(name, queries.into_iter())?;
+        collect_addresses(name, &queries, records)
+    }
+
+    /// Resolve the names of `address` with a PTR query
+    fn reverse(&self, address: IpAddr) -> ResolveResult<Vec<DomainName>> {
+        let name = DomainName::reverse_pointer(&address);
+        let record = self.resolve_specific(&name, QueryType::PTR)?;
+        ptr_names(record)
+    }
+
+    /// Resolve a single record type of `name`
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record>;
+    /// Resolve several record types of `name` at once
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>>;
 
     /// Potentially clear the cache of the actual implementation
     ///
     /// Returns Ok if implemented
+    #[allow(clippy::result_unit_err)]
     fn clear_cache(&self) -> Result<(), ()> {
         Err(())
     }
+    /// The system configuration this resolver was built from, if any
+    fn system_config(&self) -> Option<&SystemConfig> {
+        None
+    }
     /// If the system settings are cached reload them
     ///
-    /// Returns ok if implemented
+    /// Returns ok if implemented, by default the file of `system_config`
+    /// is parsed again if it changed.
+    #[allow(clippy::result_unit_err)]
     fn reload_system_config(&self) -> Result<(), ()> {
-        Err(())
+        match self.system_config() {
+            Some(config) => config.reload().map(|_| ()).map_err(|_| ()),
+            None => Err(()),
+        }
     }
 }
 
-/// An incomplete set of recored types to resolve
-pub enum QueryType {
-    AAAA,
-    A,
-    MX,
-    TXT,
+/// Flatten the addresses of all `RecordData::IpAddr` in `records`
+///
+/// Fails with NODATA for `name` and the first of `queries` if there are
+/// none so callers can rely on at least one entry. Negative answers of all
+/// queries already fail `resolve_many`, so this only triggers for answers
+/// without addresses.
+pub(crate) fn collect_addresses(
+    name: &DomainName,
+    queries: &[QueryType],
+    records: Vec<Record>,
+) -> ResolveResult<Vec<TtlEntry<IpAddr>>> {
+    let addresses: Vec<_> = records
+        .into_iter()
+        .filter_map(|record| match record.data {
+            RecordData::IpAddr(addresses) => Some((record.ttl, addresses)),
+            _ => None,
+        })
+        .flat_map(|(ttl, addresses)| {
+            addresses
+                .into_iter()
+                .map(move |value| TtlEntry { ttl, value })
+        })
+        .collect();
+    if addresses.is_empty() {
+        Err(ResolveError::NoData {
+            name: name.clone(),
+            query: queries.first().copied().unwrap_or(QueryType::A),
+            ttl: None,
+        })
+    } else {
+        Ok(addresses)
+    }
 }
 
-/// An incomplete set of the results a typical, mobile client may request
-pub enum Record {
-    /// AAAA or A single IpAddr result
-    IpAddr(IpAddr),
-    /// Many mail records
-    MX(Vec<PriorityEntry<IpAddr>>),
-    /// Many TXT records
-    TXT(Vec<String>),
+/// The names of a PTR answer
+pub(crate) fn ptr_names(record: Record) -> ResolveResult<Vec<DomainName>> {
+    match record.data {
+        RecordData::PTR(names) => Ok(names),
+        _ => Err(ResolveError::Malformed("expected PTR records".into())),
+    }
 }
 
-pub enum ResolveError {
-    /// Some lower stack Input/Output Error
-    IO(std::io::Error),
-    /// Not found
-    NotResolved,
+/// Combine the answers of single queries into the result of `resolve_many`
+///
+/// Queries without an answer are skipped as long as at least one succeeded,
+/// otherwise the first error is returned.
+pub(crate) fn collect_many<I: Iterator<Item = ResolveResult<Record>>>(
+    results: I,
+) -> ResolveResult<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut error = None;
+    for result in results {
+        match result {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match error {
+        Some(e) if records.is_empty() => Err(e),
+        _ => Ok(records),
+    }
 }
 
-pub struct PriorityEntry<T> {
-    /// TODO check RFCs for the actual datatype
-    pub priority: isize,
-    pub value: T,
+/// Look the queries up one after the other and combine the answers like
+/// [`collect_many`]
+pub(crate) async fn lookup_each<I, F>(
+    queries: I,
+    lookup: impl Fn(QueryType) -> F,
+) -> ResolveResult<Vec<Record>>
+where
+    I: Iterator<Item = QueryType>,
+    F: Future<Output = ResolveResult<Record>>,
+{
+    let mut results = Vec::new();
+    for query in queries {
+        results.push(lookup(query).await);
+    }
+    collect_many(results.into_iter())
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::cell::Cell;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// Knows two hosts, one of them only with IPv4
+    struct StaticResolver;
+
+    impl Resolver for StaticResolver {
+        fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+            let record = |ips: Vec<IpAddr>| {
+                Record::new(
+                    name.clone(),
+                    Duration::from_secs(300),
+                    RecordData::IpAddr(ips),
+                )
+            };
+            match (name.as_str(), query) {
+                ("dual.example", QueryType::AAAA) => Ok(record(vec![
+                    Ipv6Addr::LOCALHOST.into(),
+                    "2001:db8::1".parse().unwrap(),
+                ])),
+                ("dual.example", QueryType::A) | ("v4.example", QueryType::A) => {
+                    Ok(record(vec![Ipv4Addr::LOCALHOST.into()]))
+                }
+                // a broken backend answering with the alias only
+                ("alias.example", QueryType::AAAA) => Ok(Record::new(
+                    name.clone(),
+                    Duration::from_secs(300),
+                    RecordData::CNAME("dual.example".parse().unwrap()),
+                )),
+                ("dual.example", _) | ("v4.example", _) | ("alias.example", _) => {
+                    Err(ResolveError::NoData {
+                        name: name.clone(),
+                        query,
+                        ttl: None,
+                    })
+                }
+                _ => Err(ResolveError::NxDomain {
+                    name: name.clone(),
+                    query,
+                    ttl: None,
+                }),
+            }
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| self.resolve_specific(name, query)))
+        }
+    }
+
+    /// Counts its lookups in an [`Rc`] that lives across an await, so
+    /// neither the resolver nor its futures are `Send`
+    struct LocalResolver {
+        lookups: Rc<Cell<usize>>,
+    }
+
+    impl AsyncResolver for LocalResolver {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            let lookups = self.lookups.clone();
+            std::future::ready(()).await;
+            lookups.set(lookups.get() + 1);
+            StaticResolver.resolve_specific(name, query)
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            let mut results = Vec::new();
+            for query in queries {
+                results.push(self.resolve_specific(name, query).await);
+            }
+            collect_many(results.into_iter())
+        }
+    }
+
+    #[test]
+    fn combinators_take_local_resolvers() {
+        let lookups = Rc::new(Cell::new(0));
+        let local = || LocalResolver {
+            lookups: lookups.clone(),
+        };
+        let resolver = CachingResolver::new(SearchResolver::new(FallbackResolver::with_policy(
+            local(),
+            local(),
+            FallbackPolicy::always(),
+        )));
+        let name = "v4.example".parse().unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                block_on(resolver.resolve(&name)).unwrap(),
+                IpAddr::from(Ipv4Addr::LOCALHOST)
+            );
+        }
+        // AAAA on both backends, then A on the first, and the second round
+        // came from the cache
+        assert_eq!(lookups.get(), 3);
+    }
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn resolve_all_keeps_every_address() {
+        let resolver = StaticResolver;
+        let addresses = resolver
+            .resolve_all(&"dual.example".parse().unwrap())
+            .unwrap();
+        let ips: Vec<IpAddr> = addresses.iter().map(|entry| entry.value).collect();
+        assert_eq!(
+            ips,
+            [
+                IpAddr::from(Ipv6Addr::LOCALHOST),
+                "2001:db8::1".parse().unwrap(),
+                Ipv4Addr::LOCALHOST.into(),
+            ]
+        );
+        assert!(addresses
+            .iter()
+            .all(|entry| entry.ttl == Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn one_resolver_serves_many_names() {
+        let resolver = StaticResolver;
+        assert_eq!(
+            resolver.resolve(&"dual.example".parse().unwrap()).unwrap(),
+            IpAddr::from(Ipv6Addr::LOCALHOST)
+        );
+        assert_eq!(
+            resolver.resolve(&"v4.example".parse().unwrap()).unwrap(),
+            IpAddr::from(Ipv4Addr::LOCALHOST)
+        );
+        assert!(matches!(
+            resolver.resolve(&"unknown.example".parse().unwrap()),
+            Err(ResolveError::NxDomain { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_addresses_name_the_query() {
+        let resolver = StaticResolver;
+        assert!(matches!(
+            resolver.resolve_all(&"alias.example".parse().unwrap()),
+            Err(ResolveError::NoData {
+                query: QueryType::AAAA,
+                ..
+            })
+        ));
+        assert!(matches!(
+            resolver.resolve_all(&"unknown.example".parse().unwrap()),
+            Err(ResolveError::NxDomain { .. })
+        ));
+    }
 }
@@ -0,0 +1,220 @@
+//! Mail exchanger selection following RFC 5321 section 5.1
+//!
+//! MX records name hosts, not addresses. The helpers here order the
+//! exchangers by preference, shuffle exchangers of equal preference and
+//! expand every exchanger to its addresses through the same resolver.
+
+use crate::random::shuffle;
+use crate::{
+    AsyncResolver, DomainName, MxRecord, PriorityEntry, QueryType, RecordData, ResolveError,
+    ResolveResult, Resolver,
+};
+use std::net::IpAddr;
+
+/// Sort by preference and shuffle equal preferences to spread the load
+pub fn order_exchangers(mut records: Vec<MxRecord>) -> Vec<MxRecord> {
+    shuffle(&mut records);
+    // stable, so the shuffle survives within a preference
+    records.sort_by_key(|record| record.preference);
+    records
+}
+
+/// The exchangers of `domain` in the order a client should try them
+///
+/// A domain without MX records is its own exchanger with preference 0
+/// (implicit MX). A null MX (RFC 7505) means the domain does not accept mail
+/// and results in NODATA.
+fn exchangers(
+    domain: &DomainName,
+    result: ResolveResult<RecordData>,
+) -> ResolveResult<Vec<MxRecord>> {
+    let records = match result {
+        Ok(RecordData::MX(records)) => records,
+        Ok(_) => return Err(ResolveError::Malformed("expected MX records".into())),
+        Err(ResolveError::NoData { .. }) => {
+            return Ok(vec![MxRecord {
+                preference: 0,
+                exchange: domain.clone(),
+            }])
+        }
+        Err(e) => return Err(e),
+    };
+    if records.iter().any(|record| record.exchange.is_root()) {
+        return Err(ResolveError::NoData {
+            name: domain.clone(),
+            query: QueryType::MX,
+            ttl: None,
+        });
+    }
+    Ok(order_exchangers(records))
+}
+
+/// Combine the addresses of every exchanger, skipping the ones that failed
+fn expand<I>(exchangers: I) -> ResolveResult<Vec<PriorityEntry<IpAddr>>>
+where
+    I: Iterator<Item = (u16, ResolveResult<Vec<IpAddr>>)>,
+{
+    let mut addresses = Vec::new();
+    let mut error = None;
+    for (preference, result) in exchangers {
+        match result {
+            Ok(found) => addresses.extend(found.into_iter().map(|value| PriorityEntry {
+                priority: preference,
+                value,
+            })),
+            Err(e) => error = Some(e),
+        }
+    }
+    match error {
+        Some(e) if addresses.is_empty() => Err(e),
+        _ => Ok(addresses),
+    }
+}
+
+/// Resolve the mail exchangers of `domain` to addresses in delivery order
+pub fn resolve_exchangers<R: Resolver + ?Sized>(
+    resolver: &R,
+    domain: &DomainName,
+) -> ResolveResult<Vec<PriorityEntry<IpAddr>>> {
+    let result = resolver
+        .resolve_specific(domain, QueryType::MX)
+        .map(|record| record.data);
+    let exchangers = exchangers(domain, result)?;
+    expand(exchangers.into_iter().map(|mx| {
+        let addresses = resolver
+            .resolve_all(&mx.exchange)
+            .map(|entries| entries.into_iter().map(|entry| entry.value).collect());
+        (mx.preference, addresses)
+    }))
+}
+
+/// Resolve the mail exchangers of `domain` to addresses in delivery order
+pub async fn resolve_exchangers_async<R: AsyncResolver + ?Sized>(
+    resolver: &R,
+    domain: &DomainName,
+) -> ResolveResult<Vec<PriorityEntry<IpAddr>>> {
+    let result = resolver
+        .resolve_specific(domain, QueryType::MX)
+        .await
+        .map(|record| record.data);
+    let mut expanded = Vec::new();
+    for mx in exchangers(domain, result)? {
+        let addresses = resolver
+            .resolve_all(&mx.exchange)
+            .await
+            .map(|entries| entries.into_iter().map(|entry| entry.value).collect());
+        expanded.push((mx.preference, addresses));
+    }
+    expand(expanded.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{collect_many, Record};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    fn name(name: &str) -> DomainName {
+        name.parse().unwrap()
+    }
+
+    fn mx(preference: u16, exchange: &str) -> MxRecord {
+        MxRecord {
+            preference,
+            exchange: name(exchange),
+        }
+    }
+
+    #[test]
+    fn orders_by_preference_with_random_ties() {
+        let records = vec![
+            mx(20, "c.example"),
+            mx(10, "a.example"),
+            mx(10, "b.example"),
+        ];
+        let mut firsts = HashSet::new();
+        for _ in 0..64 {
+            let ordered = order_exchangers(records.clone());
+            assert_eq!(ordered[2], mx(20, "c.example"));
+            firsts.insert(ordered[0].exchange.clone());
+        }
+        assert_eq!(firsts.len(), 2);
+    }
+
+    struct Mail;
+
+    impl Resolver for Mail {
+        fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+            let data = match (name.as_str(), query) {
+                ("example.com", QueryType::MX) => RecordData::MX(vec![
+                    mx(20, "backup.example.com"),
+                    mx(10, "mx.example.com"),
+                    mx(30, "broken.example.com"),
+                ]),
+                ("null.example", QueryType::MX) => RecordData::MX(vec![mx(0, ".")]),
+                ("mx.example.com", QueryType::A) => {
+                    RecordData::IpAddr(vec!["192.0.2.10".parse().unwrap()])
+                }
+                ("backup.example.com", QueryType::AAAA) => {
+                    RecordData::IpAddr(vec!["2001:db8::20".parse().unwrap()])
+                }
+                ("implicit.example", QueryType::A) => {
+                    RecordData::IpAddr(vec!["192.0.2.30".parse().unwrap()])
+                }
+                ("broken.example.com", _) => {
+                    return Err(ResolveError::NxDomain {
+                        name: name.clone(),
+                        query,
+                        ttl: None,
+                    })
+                }
+                _ => {
+                    return Err(ResolveError::NoData {
+                        name: name.clone(),
+                        query,
+                        ttl: None,
+                    })
+                }
+            };
+            Ok(Record::new(name.clone(), Duration::from_secs(60), data))
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| self.resolve_specific(name, query)))
+        }
+    }
+
+    #[test]
+    fn expands_exchangers() {
+        let addresses = resolve_exchangers(&Mail, &name("example.com")).unwrap();
+        let expected = [
+            (10, "192.0.2.10".parse().unwrap()),
+            (20, "2001:db8::20".parse().unwrap()),
+        ];
+        let found: Vec<(u16, IpAddr)> = addresses
+            .into_iter()
+            .map(|e| (e.priority, e.value))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn implicit_and_null_mx() {
+        let addresses = resolve_exchangers(&Mail, &name("implicit.example")).unwrap();
+        assert_eq!(addresses[0].priority, 0);
+        assert_eq!(addresses[0].value, "192.0.2.30".parse::<IpAddr>().unwrap());
+
+        assert!(matches!(
+            resolve_exchangers(&Mail, &name("null.example")),
+            Err(ResolveError::NoData {
+                query: QueryType::MX,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,373 @@
+//! Validated domain names
+//!
+//! The rules follow RFC 1035 and RFC 1123: a name consists of up to 253
+//! octets in its textual form (255 on the wire), every label is between 1 and
+//! 63 octets long and made of letters, digits and hyphens. Underscores are
+//! accepted as well because service labels like `_sip._tcp` depend on them.
+//!
+//! Names read from DNS messages only have to fit the length limits, labels
+//! like `*` or the `/` of RFC 2317 are kept. Octets that have no place in a
+//! hostname are escaped as in master files (RFC 1035 section 5.1), `\.` for
+//! a dot inside a label and `\DDD` for anything unprintable.
+
+use std::fmt;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Longest label allowed by RFC 1035 section 2.3.4
+pub const MAX_LABEL_LEN: usize = 63;
+/// Longest name in its textual form without the trailing dot
+pub const MAX_NAME_LEN: usize = 253;
+
+/// A domain name that passed validation
+///
+/// Names ending with a dot are fully qualified (FQDN), all others are
+/// relative and may be expanded by a search list. Comparison and hashing
+/// ignore ASCII case.
+#[derive(Clone)]
+pub struct DomainName {
+    /// ASCII form without the trailing dot, empty for the root
+    name: String,
+    fqdn: bool,
+}
+
+impl DomainName {
+    /// Parse and validate `name`, converting Unicode labels to punycode
+    pub fn new(name: &str) -> Result<Self, NameError> {
+        if name.is_empty() {
+            return Err(NameError::Empty);
+        }
+        if name == "." {
+            return Ok(Self::root());
+        }
+
+        let (name, fqdn) = match name.strip_suffix('.') {
+            Some(stripped) => (stripped, true),
+            None => (name, false),
+        };
+
+        let name = if name.is_ascii() {
+            name.to_string()
+        } else {
+            idna::domain_to_ascii(name).map_err(|_| NameError::Idna)?
+        };
+
+        validate(&name)?;
+        Ok(DomainName { name, fqdn })
+    }
+
+    /// The root of the DNS tree, written as `.`
+    pub fn root() -> Self {
+        DomainName {
+            name: String::new(),
+            fqdn: true,
+        }
+    }
+
+    /// The `in-addr.arpa.` or `ip6.arpa.` name to query PTR records of `address`
+    pub fn reverse_pointer(address: &IpAddr) -> Self {
+        let mut name = String::with_capacity(72);
+        match address {
+            IpAddr::V4(v4) => {
+                for octet in v4.octets().iter().rev() {
+                    write!(name, "{}.", octet).unwrap();
+                }
+                name.push_str("in-addr.arpa");
+            }
+            IpAddr::V6(v6) => {
+                for octet in v6.octets().iter().rev() {
+                    write!(name, "{:x}.{:x}.", octet & 0xf, octet >> 4).unwrap();
+                }
+                name.push_str("ip6.arpa");
+            }
+        }
+        DomainName { name, fqdn: true }
+    }
+
+    /// A fully qualified name decoded from a DNS message, `name` holds
+    /// the labels escaped with [`escape_label`] and joined by dots
+    pub(crate) fn from_wire(name: String) -> Self {
+        DomainName { name, fqdn: true }
+    }
+
+    /// The ASCII form without the trailing dot
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// True if the name ended with a dot
+    pub fn is_fqdn(&self) -> bool {
+        self.fqdn
+    }
+
+    /// True for `.`
+    pub fn is_root(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    /// The same name marked as fully qualified
+    pub fn to_fqdn(&self) -> Self {
+        DomainName {
+            name: self.name.clone(),
+            fqdn: true,
+        }
+    }
+
+    /// The labels from left to right, the root has none
+    ///
+    /// Labels of names from DNS messages may contain master file escapes
+    /// like `\.` or `\032`.
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        let mut escaped = false;
+        self.name
+            .split(move |c| {
+                let separator = !escaped && c == '.';
+                escaped = !escaped && c == '\\';
+                separator
+            })
+            .filter(|label| !label.is_empty())
+    }
+
+    /// Number of labels, the root has none
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// The name with punycode labels decoded back to Unicode
+    pub fn to_unicode(&self) -> String {
+        let (name, _) = idna::domain_to_unicode(&self.name);
+        if self.fqdn {
+            name + "."
+        } else {
+            name
+        }
+    }
+}
+
+fn validate(name: &str) -> Result<(), NameError> {
+    if name.len() > MAX_NAME_LEN {
+        return Err(NameError::NameTooLong);
+    }
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err(NameError::EmptyLabel);
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(NameError::LabelTooLong);
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(NameError::InvalidCharacter(c));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(NameError::InvalidCharacter('-'));
+        }
+    }
+    Ok(())
+}
+
+/// The master file form of a label read from the wire
+pub(crate) fn escape_label(octets: &[u8]) -> String {
+    let mut label = String::with_capacity(octets.len());
+    for &octet in octets {
+        match octet {
+            b'.' | b'\\' => {
+                label.push('\\');
+                label.push(char::from(octet));
+            }
+            0x21..=0x7e => label.push(char::from(octet)),
+            _ => write!(label, "\\{:03}", octet).unwrap(),
+        }
+    }
+    label
+}
+
+/// The octets of a label, undoing the escapes of [`escape_label`]
+pub(crate) fn label_octets(label: &str) -> Vec<u8> {
+    let bytes = label.as_bytes();
+    let mut octets = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            octets.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let decimal = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_digit))
+            .and_then(|digits| std::str::from_utf8(digits).ok()?.parse().ok());
+        match decimal {
+            Some(octet) => {
+                octets.push(octet);
+                i += 4;
+            }
+            None => {
+                octets.push(bytes[i + 1]);
+                i += 2;
+            }
+        }
+    }
+    octets
+}
+
+impl FromStr for DomainName {
+    type Err = NameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DomainName::new(s)
+    }
+}
+
+impl TryFrom<&str> for DomainName {
+    type Error = NameError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        DomainName::new(s)
+    }
+}
+
+impl PartialEq for DomainName {
+    fn eq(&self, other: &Self) -> bool {
+        self.fqdn == other.fqdn && self.name.eq_ignore_ascii_case(&other.name)
+    }
+}
+
+impl Eq for DomainName {}
+
+impl Hash for DomainName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for b in self.name.bytes() {
+            state.write_u8(b.to_ascii_lowercase());
+        }
+        self.fqdn.hash(state);
+    }
+}
+
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if self.fqdn {
+            f.write_str(".")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DomainName({})", self)
+    }
+}
+
+/// Reasons a name is rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// Nothing to parse
+    Empty,
+    /// Two dots in a row or a leading dot
+    EmptyLabel,
+    /// A label longer than 63 octets
+    LabelTooLong,
+    /// The whole name is longer than 253 octets
+    NameTooLong,
+    /// The character is not allowed at this position
+    InvalidCharacter(char),
+    /// The Unicode name could not be converted to punycode
+    Idna,
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "empty domain name"),
+            NameError::EmptyLabel => write!(f, "empty label in domain name"),
+            NameError::LabelTooLong => {
+                write!(f, "label longer than {} octets", MAX_LABEL_LEN)
+            }
+            NameError::NameTooLong => {
+                write!(f, "domain name longer than {} octets", MAX_NAME_LEN)
+            }
+            NameError::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+            NameError::Idna => write!(f, "invalid internationalized domain name"),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn fqdn_and_relative() {
+        let fqdn = DomainName::new("example.com.").unwrap();
+        let relative = DomainName::new("example.com").unwrap();
+        assert!(fqdn.is_fqdn());
+        assert!(!relative.is_fqdn());
+        assert_ne!(fqdn, relative);
+        assert_eq!(relative.to_fqdn(), fqdn);
+        assert_eq!(fqdn.to_string(), "example.com.");
+        assert_eq!(fqdn.label_count(), 2);
+        assert_eq!(DomainName::new(".").unwrap(), DomainName::root());
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert_eq!(DomainName::new(""), Err(NameError::Empty));
+        assert_eq!(DomainName::new("a..b"), Err(NameError::EmptyLabel));
+        assert_eq!(DomainName::new(".a"), Err(NameError::EmptyLabel));
+        assert_eq!(
+            DomainName::new(&"a".repeat(64)),
+            Err(NameError::LabelTooLong)
+        );
+        assert!(DomainName::new(&"a".repeat(63)).is_ok());
+        let long = ["a".repeat(63).as_str(); 4].join(".");
+        assert_eq!(long.len(), 255);
+        assert_eq!(DomainName::new(&long), Err(NameError::NameTooLong));
+        assert_eq!(
+            DomainName::new("-a.example"),
+            Err(NameError::InvalidCharacter('-'))
+        );
+        assert_eq!(
+            DomainName::new("a b.example"),
+            Err(NameError::InvalidCharacter(' '))
+        );
+        assert!(DomainName::new("_sip._tcp.example").is_ok());
+    }
+
+    #[test]
+    fn idna() {
+        let name = DomainName::new("bücher.example.").unwrap();
+        assert_eq!(name.as_str(), "xn--bcher-kva.example");
+        assert_eq!(name.to_unicode(), "bücher.example.");
+    }
+
+    #[test]
+    fn reverse_pointer() {
+        let v4 = DomainName::reverse_pointer(&"192.0.2.1".parse().unwrap());
+        assert_eq!(v4.to_string(), "1.2.0.192.in-addr.arpa.");
+        let v6 = DomainName::reverse_pointer(&"2001:db8::567:89ab".parse().unwrap());
+        assert_eq!(
+            v6.to_string(),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+        assert_eq!(DomainName::new(v6.as_str()).unwrap().label_count(), 34);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let lower = DomainName::new("www.example.com").unwrap();
+        let upper = DomainName::new("WWW.Example.COM").unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(upper.as_str(), "WWW.Example.COM");
+        let set: HashSet<_> = [lower, upper].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+}
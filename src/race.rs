@@ -0,0 +1,397 @@
+//! Ask several resolvers at once and take the first answer
+//!
+//! The backends are started one after the other with a configurable
+//! stagger, so a fast primary keeps the others idle while a slow or broken
+//! one only costs the stagger delay. As soon as one backend answers the
+//! lookups of the others are dropped.
+
+use crate::timer::Delay;
+use crate::{
+    AsyncResolver, DomainName, QueryType, Record, ResolveError, ResolveResult, SendAsyncResolver,
+};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+use std::time::Duration;
+
+/// Settings of [`RaceResolver`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RaceConfig {
+    /// How long to wait before starting the next backend, zero starts all
+    /// of them at once
+    pub stagger: Duration,
+}
+
+/// Races lookups between several backends
+///
+/// A backend that fails starts the next one right away instead of waiting
+/// for the stagger. Only if every backend failed an error is returned: the
+/// first negative answer, otherwise the first error response of a server,
+/// otherwise the first error that arrived.
+pub struct RaceResolver<R> {
+    backends: Vec<R>,
+    config: RaceConfig,
+    wins: Vec<AtomicU64>,
+}
+
+/// The errors of the backends that failed so far
+#[derive(Default)]
+struct Failures {
+    negative: Option<ResolveError>,
+    server: Option<ResolveError>,
+    first: Option<ResolveError>,
+}
+
+impl Failures {
+    fn record(&mut self, error: ResolveError) {
+        let slot = match error {
+            _ if error.is_negative() => &mut self.negative,
+            ResolveError::Server { .. } => &mut self.server,
+            _ => &mut self.first,
+        };
+        slot.get_or_insert(error);
+    }
+
+    /// The most telling error, at least one has to be recorded
+    fn finish(self) -> ResolveError {
+        self.negative
+            .or(self.server)
+            .or(self.first)
+            .expect("no backend failed")
+    }
+}
+
+impl<R> RaceResolver<R> {
+    /// Panics if `backends` is empty
+    pub fn new(backends: Vec<R>) -> Self {
+        Self::with_config(backends, RaceConfig::default())
+    }
+
+    /// Panics if `backends` is empty
+    pub fn with_config(backends: Vec<R>, config: RaceConfig) -> Self {
+        assert!(!backends.is_empty(), "RaceResolver needs a backend");
+        let wins = backends.iter().map(|_| AtomicU64::new(0)).collect();
+        RaceResolver {
+            backends,
+            config,
+            wins,
+        }
+    }
+
+    pub fn backends(&self) -> &[R] {
+        &self.backends
+    }
+
+    pub fn config(&self) -> &RaceConfig {
+        &self.config
+    }
+
+    /// How many lookups each backend answered first, in the order of
+    /// [`RaceResolver::backends`]
+    pub fn wins(&self) -> Vec<u64> {
+        self.wins
+            .iter()
+            .map(|wins| wins.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    async fn race<'a, T, F: Future<Output = ResolveResult<T>>>(
+        &'a self,
+        lookup: impl Fn(&'a R) -> F,
+    ) -> ResolveResult<T> {
+        let mut running: Vec<Option<Pin<Box<F>>>> = Vec::with_capacity(self.backends.len());
+        let mut failures = Failures::default();
+        let mut next_start: Option<Delay> = None;
+        // the first backend and the one after a failure start right away
+        let mut failed = true;
+
+        let winner = poll_fn(|cx| loop {
+            let due = failed
+                || next_start
+                    .as_mut()
+                    .is_some_and(|delay| Pin::new(delay).poll(cx).is_ready());
+            let started = running.len() < self.backends.len() && due;
+            if started {
+                running.push(Some(Box::pin(lookup(&self.backends[running.len()]))));
+                next_start = Some(Delay::new(self.config.stagger));
+            }
+
+            failed = false;
+            for (index, slot) in running.iter_mut().enumerate() {
+                let Some(future) = slot else { continue };
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(Ok(value)) => return Poll::Ready(Some((index, value))),
+                    Poll::Ready(Err(e)) => {
+                        failures.record(e);
+                        *slot = None;
+                        failed = true;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            if running.len() == self.backends.len() && running.iter().all(Option::is_none) {
+                return Poll::Ready(None);
+            }
+            if !(started || failed) {
+                return Poll::Pending;
+            }
+        })
+        .await;
+
+        match winner {
+            Some((index, value)) => {
+                self.wins[index].fetch_add(1, Ordering::Relaxed);
+                Ok(value)
+            }
+            None => Err(failures.finish()),
+        }
+    }
+}
+
+impl<R: AsyncResolver> AsyncResolver for RaceResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.race(|backend| backend.resolve_specific(name, query))
+            .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let queries = &queries;
+        self.race(|backend| backend.resolve_many(name, queries.iter().copied()))
+            .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for backend in &self.backends {
+            result = backend.clear_cache().await.or(result);
+        }
+        result
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for backend in &self.backends {
+            result = backend.reload_system_config().await.or(result);
+        }
+        result
+    }
+}
+
+impl<R: SendAsyncResolver> SendAsyncResolver for RaceResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.race(|backend| SendAsyncResolver::resolve_specific(backend, name, query))
+            .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let queries = &queries;
+        self.race(|backend| SendAsyncResolver::resolve_many(backend, name, queries.iter().copied()))
+            .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for backend in &self.backends {
+            result = SendAsyncResolver::clear_cache(backend).await.or(result);
+        }
+        result
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        let mut result = Err(());
+        for backend in &self.backends {
+            result = SendAsyncResolver::reload_system_config(backend)
+                .await
+                .or(result);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::{collect_many, RecordData, ResponseCode};
+    use std::net::IpAddr;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Instant;
+
+    /// Answers after a delay, with an address or an error
+    struct Slow {
+        delay: Duration,
+        address: Option<IpAddr>,
+        error: fn(&DomainName, QueryType) -> ResolveError,
+        asked: AtomicUsize,
+    }
+
+    /// Fails with NXDOMAIN without an address
+    fn slow(millis: u64, address: Option<[u8; 4]>) -> Slow {
+        Slow {
+            delay: Duration::from_millis(millis),
+            address: address.map(IpAddr::from),
+            error: |name, query| ResolveError::NxDomain {
+                name: name.clone(),
+                query,
+                ttl: None,
+            },
+            asked: AtomicUsize::new(0),
+        }
+    }
+
+    fn failing(millis: u64, error: fn(&DomainName, QueryType) -> ResolveError) -> Slow {
+        Slow {
+            error,
+            ..slow(millis, None)
+        }
+    }
+
+    impl AsyncResolver for Slow {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            self.asked.fetch_add(1, Ordering::SeqCst);
+            Delay::new(self.delay).await;
+            match self.address {
+                Some(address) => Ok(Record::new(
+                    name.clone(),
+                    Duration::from_secs(60),
+                    RecordData::IpAddr(vec![address]),
+                )),
+                None => Err((self.error)(name, query)),
+            }
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            let mut results = Vec::new();
+            for query in queries {
+                results.push(self.resolve_specific(name, query).await);
+            }
+            collect_many(results.into_iter())
+        }
+    }
+
+    fn name() -> DomainName {
+        "www.example.com".parse().unwrap()
+    }
+
+    #[test]
+    fn fastest_backend_wins() {
+        let resolver = RaceResolver::new(vec![
+            slow(200, Some([192, 0, 2, 1])),
+            slow(10, Some([192, 0, 2, 2])),
+        ]);
+        let start = Instant::now();
+        let address = block_on(resolver.resolve(&name())).unwrap();
+        assert_eq!(address, IpAddr::from([192, 0, 2, 2]));
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(resolver.wins(), [0, 1]);
+
+        let records = block_on(resolver.resolve_all(&name())).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(resolver.wins(), [0, 2]);
+    }
+
+    #[test]
+    fn stagger_delays_the_next_backend() {
+        let config = RaceConfig {
+            stagger: Duration::from_millis(100),
+        };
+        let resolver = RaceResolver::with_config(
+            vec![
+                slow(10, Some([192, 0, 2, 1])),
+                slow(0, Some([192, 0, 2, 2])),
+            ],
+            config.clone(),
+        );
+        let address = block_on(resolver.resolve(&name())).unwrap();
+        assert_eq!(address, IpAddr::from([192, 0, 2, 1]));
+        assert_eq!(resolver.backends()[1].asked.load(Ordering::SeqCst), 0);
+
+        // a failure starts the next backend without waiting
+        let resolver =
+            RaceResolver::with_config(vec![slow(0, None), slow(0, Some([192, 0, 2, 2]))], config);
+        let start = Instant::now();
+        let address = block_on(resolver.resolve(&name())).unwrap();
+        assert_eq!(address, IpAddr::from([192, 0, 2, 2]));
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn failure_starts_the_next_backend_while_others_run() {
+        let config = RaceConfig {
+            stagger: Duration::from_millis(100),
+        };
+        let resolver = RaceResolver::with_config(
+            vec![
+                slow(1000, Some([192, 0, 2, 1])),
+                slow(0, None),
+                slow(0, Some([192, 0, 2, 3])),
+            ],
+            config,
+        );
+        let start = Instant::now();
+        let address = block_on(resolver.resolve_specific(&name(), QueryType::A)).unwrap();
+        assert_eq!(
+            address.data,
+            RecordData::IpAddr(vec![IpAddr::from([192, 0, 2, 3])])
+        );
+        // the third backend did not wait for a second stagger
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(resolver.wins(), [0, 0, 1]);
+    }
+
+    #[test]
+    fn fails_only_if_all_fail() {
+        let resolver = RaceResolver::new(vec![slow(0, None), slow(20, None)]);
+        assert!(matches!(
+            block_on(resolver.resolve_specific(&name(), QueryType::A)),
+            Err(ResolveError::NxDomain { .. })
+        ));
+        assert_eq!(resolver.wins(), [0, 0]);
+        assert_eq!(block_on(resolver.clear_cache()), Err(()));
+
+        // a server's answer tells more than a timeout, wherever it came from
+        let timeout = |name: &DomainName, query| ResolveError::Timeout {
+            name: name.clone(),
+            query,
+        };
+        let servfail = |name: &DomainName, query| ResolveError::Server {
+            rcode: ResponseCode::ServFail,
+            name: name.clone(),
+            query,
+        };
+        let resolver = RaceResolver::new(vec![
+            failing(0, timeout),
+            failing(20, servfail),
+            failing(0, timeout),
+        ]);
+        assert!(matches!(
+            block_on(resolver.resolve_specific(&name(), QueryType::A)),
+            Err(ResolveError::Server { .. })
+        ));
+        let resolver = RaceResolver::new(vec![failing(0, servfail), slow(20, None)]);
+        assert!(matches!(
+            block_on(resolver.resolve_specific(&name(), QueryType::A)),
+            Err(ResolveError::NxDomain { .. })
+        ));
+    }
+}
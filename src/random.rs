@@ -0,0 +1,29 @@
+//! Random numbers without an extra dependency
+//!
+//! The standard library seeds every `RandomState` from the operating system,
+//! hashing a counter with it gives unpredictable values that are good enough
+//! for shuffling and transaction ids.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// A value in `0..bound`, `bound` must not be zero
+pub(crate) fn random_below(bound: u64) -> u64 {
+    random_u64() % bound
+}
+
+/// Fisher-Yates shuffle
+pub(crate) fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let j = random_below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
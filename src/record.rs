@@ -0,0 +1,490 @@
+//! Record types and the data they carry
+//!
+//! Every [`Record`] holds the complete answer to one query, so the typed
+//! data is a list of entries for all types that may have more than one
+//! record per name. Types this crate does not know are kept as raw RDATA
+//! following RFC 3597.
+
+use crate::DomainName;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The record types that can be queried
+///
+/// Types compare by their code, so `Unknown(1)` equals `A`.
+#[derive(Debug, Clone, Copy)]
+pub enum QueryType {
+    AAAA,
+    A,
+    MX,
+    TXT,
+    CNAME,
+    NS,
+    PTR,
+    SOA,
+    SRV,
+    CAA,
+    TLSA,
+    SVCB,
+    HTTPS,
+    NAPTR,
+    DS,
+    DNSKEY,
+    /// Any other type by its numeric code (RFC 3597)
+    Unknown(u16),
+}
+
+impl From<u16> for QueryType {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            43 => QueryType::DS,
+            48 => QueryType::DNSKEY,
+            52 => QueryType::TLSA,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            257 => QueryType::CAA,
+            other => QueryType::Unknown(other),
+        }
+    }
+}
+
+impl From<QueryType> for u16 {
+    fn from(query: QueryType) -> Self {
+        match query {
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::DS => 43,
+            QueryType::DNSKEY => 48,
+            QueryType::TLSA => 52,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::CAA => 257,
+            QueryType::Unknown(other) => other,
+        }
+    }
+}
+
+impl PartialEq for QueryType {
+    fn eq(&self, other: &Self) -> bool {
+        u16::from(*self) == u16::from(*other)
+    }
+}
+
+impl Eq for QueryType {}
+
+impl Hash for QueryType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        u16::from(*self).hash(state);
+    }
+}
+
+/// The answer to one query together with the data needed to cache it
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// The name that was queried
+    pub name: DomainName,
+    /// The owner of the data after following all CNAMEs
+    ///
+    /// Equal to `name` if there was no alias.
+    pub canonical_name: DomainName,
+    pub class: RecordClass,
+    /// How long the record may be cached, the smallest TTL of the set
+    pub ttl: Duration,
+    /// Where the answer came from
+    pub source: RecordSource,
+    pub data: RecordData,
+}
+
+impl Record {
+    /// An `IN` record from a recursive server without aliases
+    pub fn new(name: DomainName, ttl: Duration, data: RecordData) -> Self {
+        Record {
+            canonical_name: name.clone(),
+            name,
+            class: RecordClass::IN,
+            ttl,
+            source: RecordSource::Recursive,
+            data,
+        }
+    }
+}
+
+/// The DNS class of a record, almost always `IN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordClass {
+    /// Internet
+    IN,
+    /// Chaos
+    CH,
+    /// Hesiod
+    HS,
+    Other(u16),
+}
+
+impl From<u16> for RecordClass {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => RecordClass::IN,
+            3 => RecordClass::CH,
+            4 => RecordClass::HS,
+            other => RecordClass::Other(other),
+        }
+    }
+}
+
+impl From<RecordClass> for u16 {
+    fn from(class: RecordClass) -> Self {
+        match class {
+            RecordClass::IN => 1,
+            RecordClass::CH => 3,
+            RecordClass::HS => 4,
+            RecordClass::Other(other) => other,
+        }
+    }
+}
+
+/// The origin of an answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordSource {
+    /// A server that is authoritative for the zone answered
+    Authoritative,
+    /// A recursive server answered without authority
+    Recursive,
+    /// A cache answered without asking upstream
+    Cache,
+    /// The local hosts file answered
+    HostsFile,
+}
+
+/// The typed data of an answer, one variant per [`QueryType`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    /// All addresses of an AAAA or A query
+    IpAddr(Vec<IpAddr>),
+    /// The mail exchangers of a domain
+    MX(Vec<MxRecord>),
+    /// Many TXT records, each a list of character-strings
+    TXT(Vec<TxtRecord>),
+    /// The target of an alias, there is at most one per name
+    CNAME(DomainName),
+    /// The name servers of a zone
+    NS(Vec<DomainName>),
+    /// The names an address points back to
+    PTR(Vec<DomainName>),
+    /// The start of authority of a zone
+    SOA(SoaRecord),
+    SRV(Vec<SrvRecord>),
+    CAA(Vec<CaaRecord>),
+    TLSA(Vec<TlsaRecord>),
+    SVCB(Vec<SvcbRecord>),
+    /// SVCB records specific to HTTPS (RFC 9460 section 9)
+    HTTPS(Vec<SvcbRecord>),
+    NAPTR(Vec<NaptrRecord>),
+    DS(Vec<DsRecord>),
+    DNSKEY(Vec<DnskeyRecord>),
+    /// Records of a type without typed representation
+    Unknown(Vec<UnknownRecord>),
+}
+
+/// Mail exchange (RFC 1035 section 3.3.9, RFC 5321 section 5.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxRecord {
+    /// Lower values are tried first
+    pub preference: u16,
+    /// The host accepting mail, `.` for a null MX (RFC 7505)
+    pub exchange: DomainName,
+}
+
+/// Text record (RFC 1035 section 3.3.14)
+///
+/// The RDATA is a sequence of character-strings of up to 255 bytes each.
+/// They are kept apart and as bytes, as they do not have to be UTF-8 and
+/// long values like DKIM keys are split at arbitrary positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxtRecord {
+    pub strings: Vec<Vec<u8>>,
+}
+
+impl TxtRecord {
+    /// Split `text` into character-strings of at most 255 bytes
+    pub fn from_text(text: &str) -> Self {
+        let strings = text.as_bytes().chunks(255).map(<[u8]>::to_vec).collect();
+        TxtRecord { strings }
+    }
+
+    /// All character-strings concatenated without separator
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.strings.concat()
+    }
+
+    /// The concatenated value if it is valid UTF-8
+    pub fn to_text(&self) -> Option<String> {
+        String::from_utf8(self.to_bytes()).ok()
+    }
+
+    /// The concatenated value with invalid UTF-8 replaced
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.to_bytes()).into_owned()
+    }
+}
+
+/// Start of authority (RFC 1035 section 3.3.13)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoaRecord {
+    /// The primary name server of the zone
+    pub mname: DomainName,
+    /// The mailbox of the person responsible, the first label is the user
+    pub rname: DomainName,
+    pub serial: u32,
+    pub refresh: Duration,
+    pub retry: Duration,
+    pub expire: Duration,
+    /// The TTL of negative answers (RFC 2308 section 4)
+    pub minimum: Duration,
+}
+
+/// Service location (RFC 2782)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    /// Lower values are tried first
+    pub priority: u16,
+    /// Relative chance within the same priority
+    pub weight: u16,
+    pub port: u16,
+    /// The host providing the service, `.` if it is not available
+    pub target: DomainName,
+}
+
+/// Certification authority authorization (RFC 8659)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaaRecord {
+    pub flags: u8,
+    /// Like `issue`, `issuewild` or `iodef`
+    pub tag: String,
+    pub value: Vec<u8>,
+}
+
+impl CaaRecord {
+    /// The issuer critical flag, unknown critical tags forbid issuance
+    pub fn is_critical(&self) -> bool {
+        self.flags & 0x80 != 0
+    }
+}
+
+/// TLS certificate association (RFC 6698)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsaRecord {
+    pub cert_usage: u8,
+    pub selector: u8,
+    pub matching_type: u8,
+    pub data: Vec<u8>,
+}
+
+/// Service binding (RFC 9460)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvcbRecord {
+    /// 0 marks the alias form
+    pub priority: u16,
+    pub target: DomainName,
+    /// The raw SvcParams as key and value, sorted by key
+    pub params: Vec<(u16, Vec<u8>)>,
+}
+
+impl SvcbRecord {
+    pub const KEY_ALPN: u16 = 1;
+    pub const KEY_PORT: u16 = 3;
+
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+
+    pub fn param(&self, key: u16) -> Option<&[u8]> {
+        self.params
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The protocol identifiers of the `alpn` parameter
+    pub fn alpn(&self) -> Vec<String> {
+        let mut value = self.param(Self::KEY_ALPN).unwrap_or_default();
+        let mut alpn = Vec::new();
+        while let Some((&len, rest)) = value.split_first() {
+            let len = usize::from(len).min(rest.len());
+            alpn.push(String::from_utf8_lossy(&rest[..len]).into_owned());
+            value = &rest[len..];
+        }
+        alpn
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self.param(Self::KEY_PORT)? {
+            [high, low] => Some(u16::from_be_bytes([*high, *low])),
+            _ => None,
+        }
+    }
+}
+
+/// Naming authority pointer (RFC 3403)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NaptrRecord {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: String,
+    pub services: String,
+    pub regexp: String,
+    pub replacement: DomainName,
+}
+
+/// Delegation signer (RFC 4034 section 5)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsRecord {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+/// DNSSEC public key (RFC 4034 section 2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnskeyRecord {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+impl DnskeyRecord {
+    /// The key may sign the zone's records
+    pub fn is_zone_key(&self) -> bool {
+        self.flags & 0x0100 != 0
+    }
+
+    /// The key is a key signing key (RFC 3757)
+    pub fn is_secure_entry_point(&self) -> bool {
+        self.flags & 0x0001 != 0
+    }
+}
+
+/// Opaque RDATA of an unknown type (RFC 3597)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRecord {
+    pub rtype: u16,
+    pub rdata: Vec<u8>,
+}
+
+/// The generic presentation format `\# <length> <hex>` of RFC 3597 section 5
+impl fmt::Display for UnknownRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\\# {}", self.rdata.len())?;
+        if !self.rdata.is_empty() {
+            f.write_str(" ")?;
+            for b in &self.rdata {
+                write!(f, "{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value ordered by a 16 bit priority like the MX preference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriorityEntry<T> {
+    /// Lower values are tried first
+    pub priority: u16,
+    pub value: T,
+}
+
+/// A value together with the time it may be cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TtlEntry<T> {
+    pub ttl: Duration,
+    pub value: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_codes_round_trip() {
+        for code in 0..=300u16 {
+            assert_eq!(u16::from(QueryType::from(code)), code);
+        }
+        assert_eq!(QueryType::from(65), QueryType::HTTPS);
+        assert_eq!(QueryType::from(99), QueryType::Unknown(99));
+    }
+
+    #[test]
+    fn unknown_codes_equal_known_types() {
+        use std::collections::HashSet;
+
+        assert_eq!(QueryType::Unknown(1), QueryType::A);
+        assert_ne!(QueryType::Unknown(28), QueryType::A);
+        let types: HashSet<_> = [QueryType::A, QueryType::Unknown(1), QueryType::Unknown(28)]
+            .into_iter()
+            .collect();
+        assert_eq!(types.len(), 2);
+        assert!(types.contains(&QueryType::AAAA));
+    }
+
+    #[test]
+    fn txt_chunks() {
+        let long = "p=".to_string() + &"A".repeat(400);
+        let record = TxtRecord::from_text(&long);
+        assert_eq!(record.strings.len(), 2);
+        assert_eq!(record.strings[0].len(), 255);
+        assert_eq!(record.to_text().unwrap(), long);
+
+        let binary = TxtRecord {
+            strings: vec![b"a".to_vec(), vec![0xff]],
+        };
+        assert_eq!(binary.to_bytes(), [b'a', 0xff]);
+        assert_eq!(binary.to_text(), None);
+        assert_eq!(binary.to_string_lossy(), "a\u{fffd}");
+    }
+
+    #[test]
+    fn svcb_params() {
+        let record = SvcbRecord {
+            priority: 1,
+            target: DomainName::root(),
+            params: vec![
+                (SvcbRecord::KEY_ALPN, b"\x02h2\x02h3".to_vec()),
+                (SvcbRecord::KEY_PORT, vec![0x01, 0xbb]),
+            ],
+        };
+        assert!(!record.is_alias());
+        assert_eq!(record.alpn(), ["h2", "h3"]);
+        assert_eq!(record.port(), Some(443));
+    }
+
+    #[test]
+    fn unknown_presentation() {
+        let record = UnknownRecord {
+            rtype: 731,
+            rdata: vec![0x0a, 0x00, 0x00, 0x01],
+        };
+        assert_eq!(record.to_string(), "\\# 4 0a000001");
+    }
+}
@@ -0,0 +1,108 @@
+//! Forward-confirmed reverse DNS (FCrDNS)
+//!
+//! Anybody controlling the reverse zone of an address can claim any name in
+//! its PTR records. A name is only trustworthy if it resolves back to the
+//! same address, which is what the helpers here check.
+
+use crate::{AsyncResolver, DomainName, ResolveResult, Resolver};
+use std::net::IpAddr;
+
+/// The PTR names of `address` that resolve back to `address`
+///
+/// The result is empty if no name could be confirmed, names whose forward
+/// lookup fails are skipped.
+pub fn forward_confirmed<R: Resolver + ?Sized>(
+    resolver: &R,
+    address: IpAddr,
+) -> ResolveResult<Vec<DomainName>> {
+    let names = resolver.reverse(address)?;
+    Ok(names
+        .into_iter()
+        .filter(|name| {
+            resolver
+                .resolve_all(name)
+                .is_ok_and(|entries| entries.iter().any(|entry| entry.value == address))
+        })
+        .collect())
+}
+
+/// The PTR names of `address` that resolve back to `address`
+///
+/// The result is empty if no name could be confirmed, names whose forward
+/// lookup fails are skipped.
+pub async fn forward_confirmed_async<R: AsyncResolver + ?Sized>(
+    resolver: &R,
+    address: IpAddr,
+) -> ResolveResult<Vec<DomainName>> {
+    let mut confirmed = Vec::new();
+    for name in resolver.reverse(address).await? {
+        let matches = resolver
+            .resolve_all(&name)
+            .await
+            .is_ok_and(|entries| entries.iter().any(|entry| entry.value == address));
+        if matches {
+            confirmed.push(name);
+        }
+    }
+    Ok(confirmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{collect_many, QueryType, Record, RecordData, ResolveError};
+    use std::time::Duration;
+
+    /// 192.0.2.1 claims to be both `host.example` and `bank.example`, only
+    /// the first one points back
+    struct Spoofed;
+
+    impl Resolver for Spoofed {
+        fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+            let data = match (name.as_str(), query) {
+                ("1.2.0.192.in-addr.arpa", QueryType::PTR) => RecordData::PTR(vec![
+                    "host.example.".parse().unwrap(),
+                    "bank.example.".parse().unwrap(),
+                ]),
+                ("host.example", QueryType::A) => {
+                    RecordData::IpAddr(vec!["192.0.2.1".parse().unwrap()])
+                }
+                ("bank.example", QueryType::A) => {
+                    RecordData::IpAddr(vec!["198.51.100.7".parse().unwrap()])
+                }
+                _ => {
+                    return Err(ResolveError::NxDomain {
+                        name: name.clone(),
+                        query,
+                        ttl: None,
+                    })
+                }
+            };
+            Ok(Record::new(name.clone(), Duration::from_secs(60), data))
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| self.resolve_specific(name, query)))
+        }
+    }
+
+    #[test]
+    fn reverse_lookup() {
+        let names = Spoofed.reverse("192.0.2.1".parse().unwrap()).unwrap();
+        assert_eq!(names.len(), 2);
+        assert!(matches!(
+            Spoofed.reverse("192.0.2.2".parse().unwrap()),
+            Err(ResolveError::NxDomain { .. })
+        ));
+    }
+
+    #[test]
+    fn confirms_only_matching_names() {
+        let confirmed = forward_confirmed(&Spoofed, "192.0.2.1".parse().unwrap()).unwrap();
+        assert_eq!(confirmed, ["host.example.".parse::<DomainName>().unwrap()]);
+    }
+}
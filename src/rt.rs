@@ -0,0 +1,277 @@
+//! Minimal executor helpers without a runtime dependency
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+/// How long an idle thread of the blocking pool waits for more work
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Drive `future` to completion on the current thread
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = std::pin::pin!(future);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+struct Slot<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// The result of [`spawn_blocking`]
+pub(crate) struct Blocking<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads for blocking work, started on demand and ended when idle
+struct Pool {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    idle: usize,
+}
+
+fn pool() -> &'static Pool {
+    static POOL: OnceLock<Pool> = OnceLock::new();
+    POOL.get_or_init(|| Pool {
+        queue: Mutex::new(Queue {
+            jobs: VecDeque::new(),
+            idle: 0,
+        }),
+        available: Condvar::new(),
+    })
+}
+
+impl Pool {
+    fn execute(&'static self, job: Job) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.jobs.push_back(job);
+        if queue.jobs.len() <= queue.idle {
+            self.available.notify_one();
+        } else {
+            thread::Builder::new()
+                .name("resolver_types-blocking".into())
+                .spawn(move || self.work())
+                .expect("unable to spawn blocking thread");
+        }
+    }
+
+    fn work(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(job) = queue.jobs.pop_front() {
+                drop(queue);
+                job();
+                queue = self.queue.lock().unwrap();
+                continue;
+            }
+            queue.idle += 1;
+            let (next, timeout) = self
+                .available
+                .wait_timeout_while(queue, IDLE_TIMEOUT, |queue| queue.jobs.is_empty())
+                .unwrap();
+            queue = next;
+            queue.idle -= 1;
+            if timeout.timed_out() {
+                return;
+            }
+        }
+    }
+}
+
+/// Run the blocking `work` on the thread pool and await its result
+///
+/// A panic of `work` is resumed in the task awaiting it.
+pub(crate) fn spawn_blocking<T, F>(work: F) -> Blocking<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let slot = Arc::new(Mutex::new(Slot {
+        result: None,
+        waker: None,
+    }));
+    let shared = slot.clone();
+    pool().execute(Box::new(move || {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(work));
+        let waker = {
+            let mut slot = shared.lock().unwrap();
+            slot.result = Some(result);
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }));
+    Blocking { slot }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => std::panic::resume_unwind(payload),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+enum Message {
+    Spawn(Task),
+    Wake(u64),
+    Stop,
+}
+
+/// A thread polling the futures given to [`Executor::run`]
+///
+/// The futures share the thread, so they must not block it.
+pub(crate) struct Executor {
+    sender: mpsc::Sender<Message>,
+}
+
+struct TaskWaker {
+    id: u64,
+    sender: mpsc::Sender<Message>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        // the executor is gone if sending fails, and with it the task
+        let _ = self.sender.send(Message::Wake(self.id));
+    }
+}
+
+impl Executor {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let wakers = sender.clone();
+        thread::Builder::new()
+            .name("resolver_types-executor".into())
+            .spawn(move || Executor::poll_tasks(receiver, wakers))
+            .expect("unable to spawn executor thread");
+        Executor { sender }
+    }
+
+    /// Block the current thread until `future` completed on the executor
+    ///
+    /// If `future` panics the current thread panics as well.
+    pub(crate) fn run<T, F>(&self, future: F) -> T
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let task = async move {
+            let _ = sender.send(future.await);
+        };
+        self.sender
+            .send(Message::Spawn(Box::pin(task)))
+            .expect("executor thread stopped");
+        match receiver.recv() {
+            Ok(output) => output,
+            Err(_) => panic!("future panicked on the executor thread"),
+        }
+    }
+
+    fn poll_tasks(receiver: mpsc::Receiver<Message>, wakers: mpsc::Sender<Message>) {
+        let mut tasks = HashMap::new();
+        let mut next_id = 0;
+        for message in receiver {
+            let id = match message {
+                Message::Spawn(task) => {
+                    next_id += 1;
+                    tasks.insert(next_id, task);
+                    next_id
+                }
+                Message::Wake(id) => id,
+                Message::Stop => return,
+            };
+            let Some(task) = tasks.get_mut(&id) else {
+                continue;
+            };
+            let waker = Arc::new(TaskWaker {
+                id,
+                sender: wakers.clone(),
+            })
+            .into();
+            let poll = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                task.as_mut().poll(&mut Context::from_waker(&waker))
+            }));
+            // a panicked task drops its sender, which the waiting thread notices
+            if !matches!(poll, Ok(Poll::Pending)) {
+                tasks.remove(&id);
+            }
+        }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Stop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn awaits_blocking_work() {
+        let value = block_on(spawn_blocking(|| {
+            thread::sleep(std::time::Duration::from_millis(20));
+            42
+        }));
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn runs_futures_on_the_executor() {
+        let executor = Executor::new();
+        let caller = thread::current().id();
+        let (value, thread) = executor.run(async {
+            crate::timer::Delay::new(Duration::from_millis(10)).await;
+            (42, thread::current().id())
+        });
+        assert_eq!(value, 42);
+        assert_ne!(thread, caller);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.run(async { panic!("boom") })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(executor.run(async { 7 }), 7);
+    }
+}
@@ -0,0 +1,446 @@
+//! Search list expansion of relative names like glibc's `res_search`
+//!
+//! A name ending with a dot is absolute and queried as is. Other names are
+//! tried with every search domain appended, and as they are either before
+//! (with at least `ndots` dots) or after the search domains.
+//!
+//! NXDOMAIN and NODATA move on to the next candidate, so does SERVFAIL.
+//! Any other error, a timeout for example, ends the search right away. If
+//! all candidates fail the error is picked like glibc does: the error of
+//! the name as is if it was tried first, otherwise NODATA if any candidate
+//! exists, otherwise SERVFAIL if any server failed, otherwise the last error.
+
+use crate::{
+    AsyncResolver, DomainName, QueryType, Record, ResolveError, ResolveResult, Resolver,
+    ResolverConfig, ResponseCode, SendAsyncResolver,
+};
+use std::future::Future;
+
+/// Settings of a [`SearchResolver`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchConfig {
+    /// Domains appended to relative names in order
+    pub search: Vec<DomainName>,
+    /// Names with at least this many dots are tried as is first
+    pub ndots: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            search: Vec::new(),
+            ndots: 1,
+        }
+    }
+}
+
+impl From<&ResolverConfig> for SearchConfig {
+    fn from(config: &ResolverConfig) -> Self {
+        SearchConfig {
+            search: config.search.clone(),
+            ndots: config.ndots,
+        }
+    }
+}
+
+impl SearchConfig {
+    /// The absolute names to try for `name` in order
+    pub fn candidates(&self, name: &DomainName) -> Vec<DomainName> {
+        if name.is_fqdn() {
+            return vec![name.clone()];
+        }
+
+        // names that get too long with a domain are skipped
+        let mut candidates: Vec<DomainName> = self
+            .search
+            .iter()
+            .filter(|domain| !domain.is_root())
+            .filter_map(|domain| {
+                DomainName::new(&format!("{}.{}.", name.as_str(), domain.as_str())).ok()
+            })
+            .collect();
+        if self.as_is_first(name) {
+            candidates.insert(0, name.to_fqdn());
+        } else {
+            candidates.push(name.to_fqdn());
+        }
+        candidates
+    }
+
+    fn as_is_first(&self, name: &DomainName) -> bool {
+        name.is_fqdn() || name.label_count().saturating_sub(1) >= self.ndots
+    }
+}
+
+/// The errors collected while trying candidates
+struct Failures {
+    as_is_first: bool,
+    as_is: Option<ResolveError>,
+    no_data: Option<ResolveError>,
+    server_failure: Option<ResolveError>,
+    last: Option<ResolveError>,
+}
+
+impl Failures {
+    fn new(as_is_first: bool) -> Self {
+        Failures {
+            as_is_first,
+            as_is: None,
+            no_data: None,
+            server_failure: None,
+            last: None,
+        }
+    }
+
+    /// Remember `error`, returns it back if the search has to stop
+    fn record(&mut self, error: ResolveError) -> Option<ResolveError> {
+        match &error {
+            ResolveError::NoData { .. } => {
+                self.no_data.get_or_insert_with(|| error.clone());
+            }
+            ResolveError::NxDomain { .. } => {}
+            ResolveError::Server {
+                rcode: ResponseCode::ServFail,
+                ..
+            } => {
+                self.server_failure.get_or_insert_with(|| error.clone());
+            }
+            _ => return Some(error),
+        }
+        if self.as_is_first && self.last.is_none() {
+            self.as_is = Some(error.clone());
+        }
+        self.last = Some(error);
+        None
+    }
+
+    fn finish(self, name: &DomainName, query: QueryType) -> ResolveError {
+        self.as_is
+            .or(self.no_data)
+            .or(self.server_failure)
+            .or(self.last)
+            .unwrap_or_else(|| ResolveError::NxDomain {
+                name: name.clone(),
+                query,
+                ttl: None,
+            })
+    }
+}
+
+/// Expands relative names with a search list before asking `R`
+///
+/// Records keep the expanded name that answered as their `name`.
+#[derive(Debug, Clone)]
+pub struct SearchResolver<R> {
+    inner: R,
+    config: SearchConfig,
+}
+
+impl<R> SearchResolver<R> {
+    /// Without search domains only `ndots` applies
+    pub fn new(inner: R) -> Self {
+        Self::with_config(inner, SearchConfig::default())
+    }
+
+    pub fn with_config(inner: R, config: SearchConfig) -> Self {
+        SearchResolver { inner, config }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn config(&self) -> &SearchConfig {
+        &self.config
+    }
+}
+
+impl<R> SearchResolver<R> {
+    fn search<T>(
+        &self,
+        name: &DomainName,
+        query: QueryType,
+        mut lookup: impl FnMut(&DomainName) -> ResolveResult<T>,
+    ) -> ResolveResult<T> {
+        let mut failures = Failures::new(self.config.as_is_first(name));
+        for candidate in self.config.candidates(name) {
+            match lookup(&candidate) {
+                Ok(found) => return Ok(found),
+                Err(e) => {
+                    if let Some(e) = failures.record(e) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Err(failures.finish(name, query))
+    }
+
+    /// [`SearchResolver::search`] with a lookup that owns the candidate
+    async fn search_async<T, F: Future<Output = ResolveResult<T>>>(
+        &self,
+        name: &DomainName,
+        query: QueryType,
+        lookup: impl Fn(DomainName) -> F,
+    ) -> ResolveResult<T> {
+        let mut failures = Failures::new(self.config.as_is_first(name));
+        for candidate in self.config.candidates(name) {
+            match lookup(candidate).await {
+                Ok(found) => return Ok(found),
+                Err(e) => {
+                    if let Some(e) = failures.record(e) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Err(failures.finish(name, query))
+    }
+}
+
+impl<R: Resolver> Resolver for SearchResolver<R> {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.search(name, query, |candidate| {
+            self.inner.resolve_specific(candidate, query)
+        })
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let query = queries.first().copied().unwrap_or(QueryType::A);
+        self.search(name, query, |candidate| {
+            self.inner.resolve_many(candidate, queries.iter().copied())
+        })
+    }
+
+    fn clear_cache(&self) -> Result<(), ()> {
+        self.inner.clear_cache()
+    }
+
+    fn reload_system_config(&self) -> Result<(), ()> {
+        self.inner.reload_system_config()
+    }
+}
+
+impl<R: AsyncResolver> AsyncResolver for SearchResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.search_async(name, query, |candidate| async move {
+            self.inner.resolve_specific(&candidate, query).await
+        })
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let query = queries.first().copied().unwrap_or(QueryType::A);
+        let queries = &queries;
+        self.search_async(name, query, |candidate| async move {
+            self.inner
+                .resolve_many(&candidate, queries.iter().copied())
+                .await
+        })
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        self.inner.clear_cache().await
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        self.inner.reload_system_config().await
+    }
+}
+
+impl<R: SendAsyncResolver> SendAsyncResolver for SearchResolver<R> {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.search_async(name, query, |candidate| async move {
+            SendAsyncResolver::resolve_specific(&self.inner, &candidate, query).await
+        })
+        .await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType> + Send>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let queries: Vec<_> = queries.collect();
+        let query = queries.first().copied().unwrap_or(QueryType::A);
+        let queries = &queries;
+        self.search_async(name, query, |candidate| async move {
+            SendAsyncResolver::resolve_many(&self.inner, &candidate, queries.iter().copied()).await
+        })
+        .await
+    }
+
+    async fn clear_cache(&self) -> Result<(), ()> {
+        SendAsyncResolver::clear_cache(&self.inner).await
+    }
+
+    async fn reload_system_config(&self) -> Result<(), ()> {
+        SendAsyncResolver::reload_system_config(&self.inner).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::{collect_many, RecordData};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn name(name: &str) -> DomainName {
+        name.parse().unwrap()
+    }
+
+    fn config(ndots: usize) -> SearchConfig {
+        SearchConfig {
+            search: vec![name("corp.example"), name("example")],
+            ndots,
+        }
+    }
+
+    /// Remembers the names it was asked for
+    #[derive(Default)]
+    struct Zone {
+        asked: Mutex<Vec<String>>,
+    }
+
+    impl Resolver for Zone {
+        fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+            self.asked.lock().unwrap().push(name.to_string());
+            let error = |rcode| match rcode {
+                ResponseCode::NXDomain => ResolveError::NxDomain {
+                    name: name.clone(),
+                    query,
+                    ttl: None,
+                },
+                ResponseCode::NoError => ResolveError::NoData {
+                    name: name.clone(),
+                    query,
+                    ttl: None,
+                },
+                rcode => ResolveError::Server {
+                    rcode,
+                    name: name.clone(),
+                    query,
+                },
+            };
+            match name.as_str() {
+                "db.example" => Ok(Record::new(
+                    name.clone(),
+                    Duration::from_secs(60),
+                    RecordData::IpAddr(vec!["192.0.2.1".parse().unwrap()]),
+                )),
+                "mail.corp.example" => Err(error(ResponseCode::NoError)),
+                "flaky.corp.example" => Err(error(ResponseCode::ServFail)),
+                "slow.corp.example" => Err(ResolveError::Timeout {
+                    name: name.clone(),
+                    query,
+                }),
+                _ => Err(error(ResponseCode::NXDomain)),
+            }
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| Resolver::resolve_specific(self, name, query)))
+        }
+    }
+
+    impl AsyncResolver for Zone {
+        async fn resolve_specific(
+            &self,
+            name: &DomainName,
+            query: QueryType,
+        ) -> ResolveResult<Record> {
+            Resolver::resolve_specific(self, name, query)
+        }
+
+        async fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            Resolver::resolve_many(self, name, queries)
+        }
+    }
+
+    #[test]
+    fn candidate_order() {
+        let to_strings = |names: Vec<DomainName>| -> Vec<String> {
+            names.iter().map(DomainName::to_string).collect()
+        };
+        assert_eq!(
+            to_strings(config(1).candidates(&name("db"))),
+            ["db.corp.example.", "db.example.", "db."]
+        );
+        assert_eq!(
+            to_strings(config(1).candidates(&name("db.eu"))),
+            ["db.eu.", "db.eu.corp.example.", "db.eu.example."]
+        );
+        assert_eq!(
+            to_strings(config(2).candidates(&name("db.eu"))),
+            ["db.eu.corp.example.", "db.eu.example.", "db.eu."]
+        );
+        assert_eq!(to_strings(config(1).candidates(&name("db."))), ["db."]);
+    }
+
+    #[test]
+    fn finds_the_first_match() {
+        let resolver = SearchResolver::with_config(Zone::default(), config(1));
+        let record = Resolver::resolve_specific(&resolver, &name("db"), QueryType::A).unwrap();
+        assert_eq!(record.name, name("db.example."));
+        assert_eq!(
+            *resolver.inner().asked.lock().unwrap(),
+            ["db.corp.example.", "db.example."]
+        );
+
+        let address = block_on(AsyncResolver::resolve(&resolver, &name("db"))).unwrap();
+        assert_eq!(address, "192.0.2.1".parse::<std::net::IpAddr>().unwrap());
+        assert!(Resolver::resolve(&resolver, &name("db.")).is_err());
+    }
+
+    #[test]
+    fn aggregates_errors() {
+        let resolver = SearchResolver::with_config(Zone::default(), config(1));
+        let resolve = |n: &str| Resolver::resolve_specific(&resolver, &name(n), QueryType::A);
+        // NODATA of mail.corp.example wins over the later NXDOMAINs
+        assert!(matches!(resolve("mail"), Err(ResolveError::NoData { .. })));
+        assert!(matches!(
+            resolve("flaky"),
+            Err(ResolveError::Server {
+                rcode: ResponseCode::ServFail,
+                ..
+            })
+        ));
+        assert!(matches!(
+            resolve("nothing"),
+            Err(ResolveError::NxDomain { .. })
+        ));
+
+        // a timeout ends the search
+        resolver.inner().asked.lock().unwrap().clear();
+        assert!(matches!(resolve("slow"), Err(ResolveError::Timeout { .. })));
+        assert_eq!(resolver.inner().asked.lock().unwrap().len(), 1);
+
+        // the name as is was tried first, so its error is reported
+        let resolver = SearchResolver::with_config(Zone::default(), config(0));
+        let error = Resolver::resolve_specific(&resolver, &name("mail"), QueryType::A);
+        assert!(
+            matches!(error, Err(ResolveError::NxDomain { name, .. }) if name.as_str() == "mail")
+        );
+    }
+}
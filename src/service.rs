@@ -0,0 +1,279 @@
+//! Service discovery with SRV records following RFC 2782
+//!
+//! [`SrvTargets`] yields the records in the order a client should try them:
+//! lowest priority first and, within one priority, picked at random with a
+//! chance proportional to the weight. The resolve helpers additionally look
+//! up the addresses of every target.
+
+use crate::random::random_below;
+use crate::{
+    AsyncResolver, DomainName, NameError, QueryType, RecordData, ResolveError, ResolveResult,
+    Resolver, SrvRecord,
+};
+use std::net::SocketAddr;
+
+/// Build the owner name `_service._protocol.domain` of SRV records
+///
+/// The leading underscores may be omitted.
+pub fn service_name(
+    service: &str,
+    protocol: &str,
+    domain: &DomainName,
+) -> Result<DomainName, NameError> {
+    let service = service.trim_start_matches('_');
+    let protocol = protocol.trim_start_matches('_');
+    let name = format!("_{}._{}.{}", service, protocol, domain);
+    DomainName::new(&name)
+}
+
+/// Yields SRV records in the order of RFC 2782
+#[derive(Debug, Clone)]
+pub struct SrvTargets {
+    remaining: Vec<SrvRecord>,
+}
+
+impl SrvTargets {
+    /// A single record with the target `.` means the service is not
+    /// available, in that case nothing is yielded.
+    pub fn new(records: Vec<SrvRecord>) -> Self {
+        let unavailable = records.len() == 1 && records[0].target.is_root();
+        SrvTargets {
+            remaining: if unavailable { Vec::new() } else { records },
+        }
+    }
+}
+
+impl SrvTargets {
+    /// The next record, `draw` picks a value in `0..bound`
+    fn next_with(&mut self, draw: impl FnOnce(u64) -> u64) -> Option<SrvRecord> {
+        let priority = self.remaining.iter().map(|r| r.priority).min()?;
+        let mut group: Vec<usize> = (0..self.remaining.len())
+            .filter(|&i| self.remaining[i].priority == priority)
+            .collect();
+        // records with weight 0 go first so they only win a zero draw
+        group.sort_by_key(|&i| self.remaining[i].weight != 0);
+
+        let total: u64 = group
+            .iter()
+            .map(|&i| u64::from(self.remaining[i].weight))
+            .sum();
+        let pick = draw(total + 1);
+        let mut running = 0;
+        for i in group {
+            running += u64::from(self.remaining[i].weight);
+            if running >= pick {
+                return Some(self.remaining.remove(i));
+            }
+        }
+        unreachable!("the running sum reaches the total")
+    }
+}
+
+impl Iterator for SrvTargets {
+    type Item = SrvRecord;
+
+    fn next(&mut self) -> Option<SrvRecord> {
+        self.next_with(random_below)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining.len(), Some(self.remaining.len()))
+    }
+}
+
+fn targets(name: &DomainName, result: ResolveResult<RecordData>) -> ResolveResult<SrvTargets> {
+    match result? {
+        RecordData::SRV(records) => {
+            let targets = SrvTargets::new(records);
+            if targets.remaining.is_empty() {
+                Err(ResolveError::NoData {
+                    name: name.clone(),
+                    query: QueryType::SRV,
+                    ttl: None,
+                })
+            } else {
+                Ok(targets)
+            }
+        }
+        _ => Err(ResolveError::Malformed("expected SRV records".into())),
+    }
+}
+
+/// Combine the socket addresses of all targets, skipping the ones that failed
+fn expand<I>(targets: I) -> ResolveResult<Vec<SocketAddr>>
+where
+    I: Iterator<Item = ResolveResult<Vec<SocketAddr>>>,
+{
+    let mut addresses = Vec::new();
+    let mut error = None;
+    for result in targets {
+        match result {
+            Ok(found) => addresses.extend(found),
+            Err(e) => error = Some(e),
+        }
+    }
+    match error {
+        Some(e) if addresses.is_empty() => Err(e),
+        _ => Ok(addresses),
+    }
+}
+
+/// Resolve the SRV records of `name` to socket addresses in connection order
+///
+/// `name` is the full owner name, see [`service_name`].
+pub fn resolve_service<R: Resolver + ?Sized>(
+    resolver: &R,
+    name: &DomainName,
+) -> ResolveResult<Vec<SocketAddr>> {
+    let result = resolver
+        .resolve_specific(name, QueryType::SRV)
+        .map(|record| record.data);
+    expand(targets(name, result)?.map(|srv| {
+        resolver.resolve_all(&srv.target).map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| SocketAddr::new(entry.value, srv.port))
+                .collect()
+        })
+    }))
+}
+
+/// Resolve the SRV records of `name` to socket addresses in connection order
+///
+/// `name` is the full owner name, see [`service_name`].
+pub async fn resolve_service_async<R: AsyncResolver + ?Sized>(
+    resolver: &R,
+    name: &DomainName,
+) -> ResolveResult<Vec<SocketAddr>> {
+    let result = resolver
+        .resolve_specific(name, QueryType::SRV)
+        .await
+        .map(|record| record.data);
+    let mut expanded = Vec::new();
+    for srv in targets(name, result)? {
+        let addresses = resolver.resolve_all(&srv.target).await.map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| SocketAddr::new(entry.value, srv.port))
+                .collect()
+        });
+        expanded.push(addresses);
+    }
+    expand(expanded.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{collect_many, Record};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn srv(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn builds_service_names() {
+        let domain = "example.com.".parse().unwrap();
+        assert_eq!(
+            service_name("sip", "_tcp", &domain).unwrap().to_string(),
+            "_sip._tcp.example.com."
+        );
+    }
+
+    #[test]
+    fn priority_then_weight() {
+        let records = vec![
+            srv(20, 0, 1, "backup.example"),
+            srv(10, 90, 1, "big.example"),
+            srv(10, 10, 1, "small.example"),
+        ];
+        let order: Vec<_> = SrvTargets::new(records.clone()).collect();
+        assert_eq!(order.len(), 3);
+        assert_eq!(order[2].target.as_str(), "backup.example");
+
+        // every possible draw once, the weights are the share of draws won
+        let mut firsts: HashMap<String, u64> = HashMap::new();
+        for draw in 0..=100 {
+            let mut targets = SrvTargets::new(records.clone());
+            let first = targets.next_with(|bound| {
+                assert_eq!(bound, 101);
+                draw
+            });
+            *firsts.entry(first.unwrap().target.to_string()).or_default() += 1;
+            assert_eq!(targets.next().unwrap().priority, 10);
+            assert_eq!(targets.next().unwrap().target.as_str(), "backup.example");
+        }
+        assert_eq!(firsts["big.example"], 91);
+        assert_eq!(firsts["small.example"], 10);
+
+        // weight zero only wins a zero draw
+        let records = vec![srv(10, 0, 1, "zero.example"), srv(10, 5, 1, "five.example")];
+        let first = |draw| {
+            SrvTargets::new(records.clone())
+                .next_with(|_| draw)
+                .unwrap()
+        };
+        assert_eq!(first(0).target.as_str(), "zero.example");
+        assert_eq!(first(1).target.as_str(), "five.example");
+    }
+
+    #[test]
+    fn unavailable_service() {
+        assert_eq!(SrvTargets::new(vec![srv(0, 0, 0, ".")]).count(), 0);
+    }
+
+    struct Services;
+
+    impl Resolver for Services {
+        fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+            let data = match (name.as_str(), query) {
+                ("_grpc._tcp.example.com", QueryType::SRV) => RecordData::SRV(vec![
+                    srv(20, 0, 9000, "backup.example.com"),
+                    srv(10, 0, 8443, "primary.example.com"),
+                ]),
+                ("primary.example.com", QueryType::A) => {
+                    RecordData::IpAddr(vec!["192.0.2.1".parse().unwrap()])
+                }
+                ("backup.example.com", QueryType::AAAA) => {
+                    RecordData::IpAddr(vec!["2001:db8::2".parse().unwrap()])
+                }
+                _ => {
+                    return Err(ResolveError::NoData {
+                        name: name.clone(),
+                        query,
+                        ttl: None,
+                    })
+                }
+            };
+            Ok(Record::new(name.clone(), Duration::from_secs(60), data))
+        }
+
+        fn resolve_many<I: Iterator<Item = QueryType>>(
+            &self,
+            name: &DomainName,
+            queries: I,
+        ) -> ResolveResult<Vec<Record>> {
+            collect_many(queries.map(|query| self.resolve_specific(name, query)))
+        }
+    }
+
+    #[test]
+    fn expands_targets() {
+        let name = service_name("grpc", "tcp", &"example.com".parse().unwrap()).unwrap();
+        let addresses = resolve_service(&Services, &name).unwrap();
+        assert_eq!(
+            addresses,
+            [
+                "192.0.2.1:8443".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::2]:9000".parse().unwrap(),
+            ]
+        );
+    }
+}
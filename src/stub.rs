@@ -0,0 +1,533 @@
+//! A built-in stub resolver speaking DNS over UDP and TCP
+//!
+//! The stub sends recursive queries to the configured name servers and
+//! leaves the actual resolution to them. Queries go out over UDP first and
+//! are repeated over TCP if the answer was truncated. Responses are only
+//! accepted if transaction ID and question match the query, anything else
+//! arriving on the socket is ignored.
+
+use crate::random::random_u64;
+use crate::rt::spawn_blocking;
+use crate::wire::{Edns, Message};
+use crate::{
+    collect_many, AsyncResolver, DomainName, QueryType, Record, ResolveError, ResolveResult,
+    Resolver, ResolverConfig, SendAsyncResolver, SystemConfig,
+};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// glibc waits at least a second for an answer, even with `timeout:0`
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A resolver asking name servers directly
+///
+/// Search list and `ndots` of the configuration are not applied here, the
+/// stub always asks for the name as given. The async implementation runs
+/// each lookup on a thread of its own, so it works with any executor.
+#[derive(Debug, Clone)]
+pub struct StubResolver {
+    source: Source,
+    /// Where the next query starts in the server list if `rotate` is set
+    rotation: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    Fixed(Arc<ResolverConfig>),
+    System(Arc<SystemConfig>),
+}
+
+impl StubResolver {
+    pub fn new(config: ResolverConfig) -> Self {
+        StubResolver {
+            source: Source::Fixed(Arc::new(config)),
+            rotation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Follow the file of `config`, it is read again on
+    /// `reload_system_config`
+    pub fn from_system(config: SystemConfig) -> Self {
+        StubResolver {
+            source: Source::System(Arc::new(config)),
+            rotation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Follow `/etc/resolv.conf`
+    pub fn system() -> io::Result<Self> {
+        Ok(StubResolver::from_system(SystemConfig::system()?))
+    }
+
+    /// The configuration used for the next lookup
+    pub fn config(&self) -> Arc<ResolverConfig> {
+        match &self.source {
+            Source::Fixed(config) => config.clone(),
+            Source::System(system) => system.config(),
+        }
+    }
+
+    fn followed(&self) -> Option<&SystemConfig> {
+        match &self.source {
+            Source::Fixed(_) => None,
+            Source::System(system) => Some(system),
+        }
+    }
+
+    fn lookup(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        let config = self.config();
+        let servers = &config.nameservers;
+        if servers.is_empty() {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "no name servers configured").into(),
+            );
+        }
+        let start = if config.rotate && !servers.is_empty() {
+            self.rotation.fetch_add(1, Ordering::Relaxed) % servers.len()
+        } else {
+            0
+        };
+
+        let mut error = ResolveError::Timeout {
+            name: name.clone(),
+            query,
+        };
+        for _ in 0..config.attempts.max(1) {
+            for server in servers.iter().cycle().skip(start).take(servers.len()) {
+                let result = exchange(&config, *server, name, query)
+                    .and_then(|response| response.into_record(name, query));
+                match result {
+                    Err(e) if e.is_retryable() => error = e,
+                    result => return result,
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+fn exchange(
+    config: &ResolverConfig,
+    server: SocketAddr,
+    name: &DomainName,
+    query: QueryType,
+) -> ResolveResult<Message> {
+    let mut request = Message::query(random_u64() as u16, name, query);
+    if config.edns0 {
+        request.edns = Some(Edns::default());
+    }
+    // RFC 6840 section 5.7, ask for the AD bit
+    request.authentic_data = config.trust_ad;
+
+    let timeout = config.timeout.max(MIN_TIMEOUT);
+    let response = exchange_udp(server, &request, timeout)?;
+    if response.truncated {
+        exchange_tcp(server, &request, timeout)
+    } else {
+        Ok(response)
+    }
+}
+
+fn exchange_udp(
+    server: SocketAddr,
+    request: &Message,
+    timeout: Duration,
+) -> ResolveResult<Message> {
+    let local: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0))?;
+    socket.connect(server)?;
+    socket.send(&request.encode())?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0; 65535];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(timed_out(request));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.recv(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if is_timeout(&e) => return Err(timed_out(request)),
+            Err(e) => return Err(e.into()),
+        };
+        // late answers to earlier queries and spoofing attempts are skipped,
+        // a broken answer to this query is an error
+        match Message::decode(&buffer[..len]) {
+            Ok(response) if answers(request, &response) => return Ok(response),
+            Ok(_) => continue,
+            Err(e) => match Message::decode_question(&buffer[..len]) {
+                Ok(response) if answers(request, &response) => return Err(e),
+                _ => continue,
+            },
+        }
+    }
+}
+
+fn exchange_tcp(
+    server: SocketAddr,
+    request: &Message,
+    timeout: Duration,
+) -> ResolveResult<Message> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let bytes = request.encode();
+    let mut framed = Vec::with_capacity(bytes.len() + 2);
+    framed.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    framed.extend_from_slice(&bytes);
+    stream.write_all(&framed)?;
+
+    let read = |stream: &mut TcpStream| -> io::Result<Vec<u8>> {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut buffer = vec![0; usize::from(u16::from_be_bytes(len))];
+        stream.read_exact(&mut buffer)?;
+        Ok(buffer)
+    };
+    let buffer = match read(&mut stream) {
+        Ok(buffer) => buffer,
+        Err(e) if is_timeout(&e) => return Err(timed_out(request)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let response = Message::decode(&buffer)?;
+    if answers(request, &response) {
+        Ok(response)
+    } else {
+        Err(ResolveError::Malformed(
+            "response does not match the query".into(),
+        ))
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn timed_out(request: &Message) -> ResolveError {
+    let question = &request.questions[0];
+    ResolveError::Timeout {
+        name: question.name.clone(),
+        query: question.qtype,
+    }
+}
+
+/// True if `response` belongs to `request`
+fn answers(request: &Message, response: &Message) -> bool {
+    response.response && response.id == request.id && response.questions == request.questions
+}
+
+impl Resolver for StubResolver {
+    fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        self.lookup(name, query)
+    }
+
+    fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        collect_many(queries.map(|query| self.lookup(name, query)))
+    }
+
+    fn system_config(&self) -> Option<&SystemConfig> {
+        self.followed()
+    }
+}
+
+impl AsyncResolver for StubResolver {
+    async fn resolve_specific(&self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        let stub = self.clone();
+        let name = name.clone();
+        spawn_blocking(move || stub.lookup(&name, query)).await
+    }
+
+    async fn resolve_many<I: Iterator<Item = QueryType>>(
+        &self,
+        name: &DomainName,
+        queries: I,
+    ) -> ResolveResult<Vec<Record>> {
+        let stub = self.clone();
+        let name = name.clone();
+        let queries: Vec<_> = queries.collect();
+        spawn_blocking(move || Resolver::resolve_many(&stub, &name, queries.into_iter())).await
+    }
+
+    fn system_config(&self) -> Option<&SystemConfig> {
+        self.followed()
+    }
+}
+
+impl SendAsyncResolver for StubResolver {
+    crate::forward_async_resolver!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use crate::wire::{Rdata, ResourceRecord};
+    use crate::{RecordClass, RecordData, ResponseCode, TxtRecord};
+    use std::net::TcpListener;
+    use std::thread;
+
+    type Handler = fn(&Message, bool) -> Vec<Message>;
+
+    /// Answer queries on a local UDP and TCP port, the flag tells the
+    /// handler whether the query came in over TCP
+    fn serve(handler: Handler) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(address).unwrap();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut buffer) {
+                let query = Message::decode(&buffer[..len]).unwrap();
+                for response in handler(&query, false) {
+                    udp.send_to(&response.encode(), peer).unwrap();
+                }
+            }
+        });
+        thread::spawn(move || {
+            for mut stream in tcp.incoming().flatten() {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut buffer = vec![0; usize::from(u16::from_be_bytes(len))];
+                stream.read_exact(&mut buffer).unwrap();
+                let query = Message::decode(&buffer).unwrap();
+                for response in handler(&query, true) {
+                    let bytes = response.encode();
+                    stream
+                        .write_all(&(bytes.len() as u16).to_be_bytes())
+                        .unwrap();
+                    stream.write_all(&bytes).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    fn stub(nameservers: Vec<SocketAddr>) -> StubResolver {
+        StubResolver::new(ResolverConfig {
+            nameservers,
+            timeout: MIN_TIMEOUT,
+            attempts: 1,
+            ..ResolverConfig::default()
+        })
+    }
+
+    fn reply(query: &Message, rcode: ResponseCode, answers: Vec<ResourceRecord>) -> Message {
+        let mut response = query.reply(rcode);
+        response.answers = answers;
+        response
+    }
+
+    fn rr(name: &str, ttl: u32, data: Rdata) -> ResourceRecord {
+        ResourceRecord {
+            name: name.parse().unwrap(),
+            class: RecordClass::IN,
+            ttl,
+            data,
+        }
+    }
+
+    fn zone(query: &Message, _tcp: bool) -> Vec<Message> {
+        let question = &query.questions[0];
+        let response = match (question.name.as_str(), question.qtype) {
+            ("www.example.com", QueryType::A) => reply(
+                query,
+                ResponseCode::NoError,
+                vec![
+                    rr(
+                        "www.example.com.",
+                        300,
+                        Rdata::CNAME("host.example.com.".parse().unwrap()),
+                    ),
+                    rr(
+                        "host.example.com.",
+                        30,
+                        Rdata::A(Ipv4Addr::new(192, 0, 2, 1)),
+                    ),
+                ],
+            ),
+            ("www.example.com", _) => reply(query, ResponseCode::NoError, vec![]),
+            ("broken.example.com", _) => reply(query, ResponseCode::ServFail, vec![]),
+            _ => reply(query, ResponseCode::NXDomain, vec![]),
+        };
+        vec![response]
+    }
+
+    #[test]
+    fn follows_cname_records() {
+        let resolver = stub(vec![serve(zone)]);
+        let name = "www.example.com".parse().unwrap();
+        let record = Resolver::resolve_specific(&resolver, &name, QueryType::A).unwrap();
+        assert_eq!(record.canonical_name.as_str(), "host.example.com");
+        assert_eq!(record.ttl, Duration::from_secs(30));
+        assert_eq!(
+            record.data,
+            RecordData::IpAddr(vec!["192.0.2.1".parse().unwrap()])
+        );
+
+        let all = Resolver::resolve_all(&resolver, &name).unwrap();
+        assert_eq!(all.len(), 1);
+    }
+
+    #[test]
+    fn maps_response_codes() {
+        let resolver = stub(vec![serve(zone)]);
+        let resolve = |name: &str, query| {
+            Resolver::resolve_specific(&resolver, &name.parse().unwrap(), query)
+        };
+        assert!(matches!(
+            resolve("www.example.com", QueryType::AAAA),
+            Err(ResolveError::NoData { .. })
+        ));
+        assert!(matches!(
+            resolve("missing.example.com", QueryType::A),
+            Err(ResolveError::NxDomain { .. })
+        ));
+        assert!(matches!(
+            resolve("broken.example.com", QueryType::A),
+            Err(ResolveError::Server {
+                rcode: ResponseCode::ServFail,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn ignores_foreign_responses() {
+        let server = serve(|query, _| {
+            let answer = vec![rr(
+                "example.com.",
+                60,
+                Rdata::A(Ipv4Addr::new(192, 0, 2, 7)),
+            )];
+            let mut wrong_id = reply(query, ResponseCode::NoError, vec![]);
+            wrong_id.id = query.id.wrapping_add(1);
+            let mut wrong_question = reply(query, ResponseCode::NXDomain, vec![]);
+            wrong_question.questions[0].name = "other.example.com.".parse().unwrap();
+            vec![
+                wrong_id,
+                wrong_question,
+                reply(query, ResponseCode::NoError, answer),
+            ]
+        });
+        let resolver = stub(vec![server]);
+        let address = Resolver::resolve(&resolver, &"example.com".parse().unwrap()).unwrap();
+        assert_eq!(address, IpAddr::from([192, 0, 2, 7]));
+    }
+
+    #[test]
+    fn reports_broken_answers() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = udp.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0; 512];
+            let (len, peer) = udp.recv_from(&mut buffer).unwrap();
+            let query = Message::decode(&buffer[..len]).unwrap();
+            let answer = vec![rr(
+                "example.com.",
+                60,
+                Rdata::A(Ipv4Addr::new(192, 0, 2, 7)),
+            )];
+            // cut off in the middle of the address
+            let mut foreign = reply(&query, ResponseCode::NoError, answer.clone());
+            foreign.id = query.id.wrapping_add(1);
+            let foreign = foreign.encode();
+            udp.send_to(&foreign[..foreign.len() - 2], peer).unwrap();
+            let broken = reply(&query, ResponseCode::NoError, answer).encode();
+            udp.send_to(&broken[..broken.len() - 2], peer).unwrap();
+        });
+        let resolver = stub(vec![server]);
+        assert!(matches!(
+            Resolver::resolve(&resolver, &"example.com".parse().unwrap()),
+            Err(ResolveError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn retries_truncated_answers_over_tcp() {
+        let server = serve(|query, tcp| {
+            if !tcp {
+                let mut truncated = reply(query, ResponseCode::NoError, vec![]);
+                truncated.truncated = true;
+                return vec![truncated];
+            }
+            let txt = TxtRecord::from_text(&"x".repeat(1000));
+            vec![reply(
+                query,
+                ResponseCode::NoError,
+                vec![rr("example.com.", 60, Rdata::TXT(txt))],
+            )]
+        });
+        let resolver = stub(vec![server]);
+        let record =
+            Resolver::resolve_specific(&resolver, &"example.com".parse().unwrap(), QueryType::TXT)
+                .unwrap();
+        match record.data {
+            RecordData::TXT(txt) => assert_eq!(txt[0].to_bytes().len(), 1000),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tries_the_next_server() {
+        // bound but never answering
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = stub(vec![silent.local_addr().unwrap(), serve(zone)]);
+        let name = "www.example.com".parse().unwrap();
+        let address = block_on(AsyncResolver::resolve(&resolver, &name)).unwrap();
+        assert_eq!(address, IpAddr::from([192, 0, 2, 1]));
+    }
+
+    #[test]
+    fn rejects_unusable_configs() {
+        let name = "www.example.com".parse().unwrap();
+        match Resolver::resolve(&stub(Vec::new()), &name) {
+            Err(ResolveError::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // like `options timeout:0`
+        let resolver = StubResolver::new(ResolverConfig {
+            nameservers: vec![serve(zone)],
+            timeout: Duration::ZERO,
+            ..ResolverConfig::default()
+        });
+        assert_eq!(
+            Resolver::resolve(&resolver, &name).unwrap(),
+            IpAddr::from([192, 0, 2, 1])
+        );
+    }
+
+    #[test]
+    fn reloads_the_system_config() {
+        let path =
+            std::env::temp_dir().join(format!("resolver_types-{}-stub.conf", std::process::id()));
+        std::fs::write(&path, "nameserver 127.0.0.1\noptions timeout:1\n").unwrap();
+        let resolver = StubResolver::from_system(SystemConfig::open(&path).unwrap());
+        assert_eq!(resolver.config().timeout, Duration::from_secs(1));
+
+        std::fs::write(&path, "nameserver 192.0.2.1\n").unwrap();
+        assert_eq!(Resolver::reload_system_config(&resolver), Ok(()));
+        assert_eq!(
+            resolver.config().nameservers,
+            ["192.0.2.1:53".parse().unwrap()]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! A runtime agnostic delay future
+//!
+//! All pending delays share one background thread that sleeps until the
+//! next deadline and wakes the tasks that are due. This keeps the crate free
+//! of a dependency on a specific executor.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Completes once the deadline passed
+pub(crate) struct Delay {
+    deadline: Instant,
+    shared: Option<Arc<Shared>>,
+}
+
+struct Shared {
+    fired: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+struct Entry {
+    deadline: Instant,
+    shared: Arc<Shared>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+impl Eq for Entry {}
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.deadline.cmp(&other.deadline)
+    }
+}
+
+struct Timer {
+    queue: Mutex<BinaryHeap<Reverse<Entry>>>,
+    changed: Condvar,
+}
+
+impl Delay {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Delay {
+            deadline: Instant::now() + duration,
+            shared: None,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.shared {
+            Some(shared) => {
+                if shared.fired.load(Ordering::Acquire) {
+                    return Poll::Ready(());
+                }
+                *shared.waker.lock().unwrap() = Some(cx.waker().clone());
+                // the timer may have fired between the check and the update
+                if shared.fired.load(Ordering::Acquire) {
+                    return Poll::Ready(());
+                }
+            }
+            None => {
+                let shared = Arc::new(Shared {
+                    fired: AtomicBool::new(false),
+                    waker: Mutex::new(Some(cx.waker().clone())),
+                });
+                let timer = timer();
+                timer.queue.lock().unwrap().push(Reverse(Entry {
+                    deadline: self.deadline,
+                    shared: shared.clone(),
+                }));
+                timer.changed.notify_one();
+                self.shared = Some(shared);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let timer: &'static Timer = Box::leak(Box::new(Timer {
+            queue: Mutex::new(BinaryHeap::new()),
+            changed: Condvar::new(),
+        }));
+        std::thread::Builder::new()
+            .name("resolver_types-timer".into())
+            .spawn(move || run(timer))
+            .expect("unable to spawn timer thread");
+        timer
+    })
+}
+
+fn run(timer: &Timer) {
+    let mut queue = timer.queue.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        while queue.peek().is_some_and(|entry| entry.0.deadline <= now) {
+            let Reverse(entry) = queue.pop().unwrap();
+            entry.shared.fired.store(true, Ordering::Release);
+            let waker = entry.shared.waker.lock().unwrap().take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        queue = match queue.peek() {
+            Some(entry) => {
+                let timeout = entry.0.deadline.saturating_duration_since(now);
+                timer.changed.wait_timeout(queue, timeout).unwrap().0
+            }
+            None => timer.changed.wait(queue).unwrap(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_completes() {
+        let start = Instant::now();
+        crate::rt::block_on(async {
+            let short = Delay::new(Duration::from_millis(10));
+            let long = Delay::new(Duration::from_millis(30));
+            long.await;
+            short.await;
+        });
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
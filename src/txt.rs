@@ -0,0 +1,488 @@
+//! Typed views of TXT based mail policies
+//!
+//! All parsers take a [`TxtRecord`] and join its character-strings before
+//! parsing, so values split over several strings like long DKIM keys are
+//! read as one.
+
+use crate::{DomainName, TxtRecord};
+use std::fmt;
+use std::time::Duration;
+
+/// Why a TXT record is not a valid policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxtParseError {
+    /// The record is not valid UTF-8
+    NotUtf8,
+    /// The version tag is missing or does not match, the record is
+    /// probably meant for something else
+    WrongVersion,
+    /// A required tag is missing
+    MissingTag(&'static str),
+    /// A tag or term can not be parsed
+    Invalid(String),
+}
+
+impl fmt::Display for TxtParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxtParseError::NotUtf8 => write!(f, "record is not valid UTF-8"),
+            TxtParseError::WrongVersion => write!(f, "unexpected version"),
+            TxtParseError::MissingTag(tag) => write!(f, "missing tag {:?}", tag),
+            TxtParseError::Invalid(term) => write!(f, "invalid term {:?}", term),
+        }
+    }
+}
+
+impl std::error::Error for TxtParseError {}
+
+fn text(record: &TxtRecord) -> Result<String, TxtParseError> {
+    record.to_text().ok_or(TxtParseError::NotUtf8)
+}
+
+/// Split a `tag=value; tag=value` list (RFC 6376 section 3.2)
+fn tag_list(text: &str) -> Result<Vec<(&str, &str)>, TxtParseError> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| match spec.split_once('=') {
+            Some((tag, value)) if !tag.trim().is_empty() => Ok((tag.trim(), value.trim())),
+            _ => Err(TxtParseError::Invalid(spec.to_string())),
+        })
+        .collect()
+}
+
+fn find<'a>(tags: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+        .map(|(_, value)| *value)
+}
+
+/// Check that the first tag is the version tag with `expected`
+fn check_version(tags: &[(&str, &str)], expected: &str) -> Result<(), TxtParseError> {
+    match tags.first() {
+        Some((tag, version)) if *tag == "v" && version.eq_ignore_ascii_case(expected) => Ok(()),
+        _ => Err(TxtParseError::WrongVersion),
+    }
+}
+
+/// The result a matching SPF mechanism yields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfQualifier {
+    /// `+`, the default
+    Pass,
+    /// `-`
+    Fail,
+    /// `~`
+    SoftFail,
+    /// `?`
+    Neutral,
+}
+
+/// One term of an SPF record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpfTerm {
+    /// Like `-all`, `ip4:192.0.2.0/24` or `a/24`
+    Mechanism {
+        qualifier: SpfQualifier,
+        /// `all`, `include`, `a`, `mx`, `ptr`, `ip4`, `ip6` or `exists`
+        name: String,
+        /// The text after `:`, or starting at `/` for a bare prefix length
+        argument: Option<String>,
+    },
+    /// Like `redirect=_spf.example.com`
+    Modifier { name: String, value: String },
+}
+
+/// Sender policy framework record (RFC 7208)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpfRecord {
+    pub terms: Vec<SpfTerm>,
+}
+
+impl SpfRecord {
+    const MECHANISMS: [&'static str; 8] =
+        ["all", "include", "a", "mx", "ptr", "ip4", "ip6", "exists"];
+
+    pub fn from_txt(record: &TxtRecord) -> Result<Self, TxtParseError> {
+        let text = text(record)?;
+        let mut terms = text.split_ascii_whitespace();
+        match terms.next() {
+            Some(version) if version.eq_ignore_ascii_case("v=spf1") => {}
+            _ => return Err(TxtParseError::WrongVersion),
+        }
+        let terms = terms.map(Self::term).collect::<Result<_, _>>()?;
+        Ok(SpfRecord { terms })
+    }
+
+    fn term(term: &str) -> Result<SpfTerm, TxtParseError> {
+        let invalid = || TxtParseError::Invalid(term.to_string());
+
+        let name_end = term
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "-_.".contains(c)))
+            .unwrap_or(term.len());
+        if term[name_end..].starts_with('=') {
+            if name_end == 0 {
+                return Err(invalid());
+            }
+            return Ok(SpfTerm::Modifier {
+                name: term[..name_end].to_string(),
+                value: term[name_end + 1..].to_string(),
+            });
+        }
+
+        let (qualifier, mechanism) = match term.as_bytes().first() {
+            Some(b'+') => (SpfQualifier::Pass, &term[1..]),
+            Some(b'-') => (SpfQualifier::Fail, &term[1..]),
+            Some(b'~') => (SpfQualifier::SoftFail, &term[1..]),
+            Some(b'?') => (SpfQualifier::Neutral, &term[1..]),
+            _ => (SpfQualifier::Pass, term),
+        };
+        let (name, argument) = match mechanism.find([':', '/']) {
+            Some(i) if mechanism.as_bytes()[i] == b':' => {
+                (&mechanism[..i], Some(mechanism[i + 1..].to_string()))
+            }
+            Some(i) => (&mechanism[..i], Some(mechanism[i..].to_string())),
+            None => (mechanism, None),
+        };
+        let name = name.to_ascii_lowercase();
+        if !Self::MECHANISMS.contains(&name.as_str()) {
+            return Err(invalid());
+        }
+        Ok(SpfTerm::Mechanism {
+            qualifier,
+            name,
+            argument,
+        })
+    }
+}
+
+/// DKIM public key record (RFC 6376 section 3.6.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DkimKey {
+    /// `rsa` unless stated otherwise
+    pub key_type: String,
+    /// Base64 encoded key without whitespace, empty if revoked
+    pub public_key: String,
+    /// Acceptable hash algorithms, empty means all
+    pub hash_algorithms: Vec<String>,
+    /// Flags like `y` for testing and `s` for strict subdomain handling
+    pub flags: Vec<String>,
+}
+
+impl DkimKey {
+    pub fn from_txt(record: &TxtRecord) -> Result<Self, TxtParseError> {
+        let text = text(record)?;
+        let tags = tag_list(&text)?;
+        if find(&tags, "v").is_some() {
+            check_version(&tags, "DKIM1")?;
+        }
+        let list = |value: Option<&str>| -> Vec<String> {
+            value
+                .map(|v| {
+                    v.split(':')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let public_key = find(&tags, "p").ok_or(TxtParseError::MissingTag("p"))?;
+        Ok(DkimKey {
+            key_type: find(&tags, "k").unwrap_or("rsa").to_string(),
+            public_key: public_key.split_whitespace().collect(),
+            hash_algorithms: list(find(&tags, "h")),
+            flags: list(find(&tags, "t")),
+        })
+    }
+
+    /// An empty key means the key was revoked
+    pub fn is_revoked(&self) -> bool {
+        self.public_key.is_empty()
+    }
+}
+
+/// What receivers should do with mail failing DMARC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmarcPolicy {
+    None,
+    Quarantine,
+    Reject,
+}
+
+impl DmarcPolicy {
+    fn parse(value: &str) -> Result<Self, TxtParseError> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" => Ok(DmarcPolicy::None),
+            "quarantine" => Ok(DmarcPolicy::Quarantine),
+            "reject" => Ok(DmarcPolicy::Reject),
+            _ => Err(TxtParseError::Invalid(value.to_string())),
+        }
+    }
+}
+
+/// DMARC policy record (RFC 7489 section 6.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmarcRecord {
+    pub policy: DmarcPolicy,
+    /// The policy for subdomains, `policy` applies if missing
+    pub subdomain_policy: Option<DmarcPolicy>,
+    /// Percentage of messages the policy applies to
+    pub percentage: u8,
+    /// Strict instead of relaxed DKIM alignment
+    pub strict_dkim: bool,
+    /// Strict instead of relaxed SPF alignment
+    pub strict_spf: bool,
+    /// Addresses for aggregate reports
+    pub aggregate_reports: Vec<String>,
+    /// Addresses for failure reports
+    pub failure_reports: Vec<String>,
+}
+
+impl DmarcRecord {
+    pub fn from_txt(record: &TxtRecord) -> Result<Self, TxtParseError> {
+        let text = text(record)?;
+        let tags = tag_list(&text)?;
+        check_version(&tags, "DMARC1")?;
+
+        let invalid = |value: &str| TxtParseError::Invalid(value.to_string());
+        let strict = |tag| match find(&tags, tag) {
+            None | Some("r") => Ok(false),
+            Some("s") => Ok(true),
+            Some(other) => Err(invalid(other)),
+        };
+        let uris = |tag| {
+            find(&tags, tag)
+                .map(|value| value.split(',').map(|uri| uri.trim().to_string()).collect())
+                .unwrap_or_default()
+        };
+        let percentage = match find(&tags, "pct") {
+            Some(pct) => pct
+                .parse()
+                .ok()
+                .filter(|pct| *pct <= 100)
+                .ok_or_else(|| invalid(pct))?,
+            None => 100,
+        };
+
+        Ok(DmarcRecord {
+            policy: DmarcPolicy::parse(find(&tags, "p").ok_or(TxtParseError::MissingTag("p"))?)?,
+            subdomain_policy: find(&tags, "sp").map(DmarcPolicy::parse).transpose()?,
+            percentage,
+            strict_dkim: strict("adkim")?,
+            strict_spf: strict("aspf")?,
+            aggregate_reports: uris("rua"),
+            failure_reports: uris("ruf"),
+        })
+    }
+}
+
+/// The `_mta-sts` TXT record announcing a policy (RFC 8461 section 3.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtaStsRecord {
+    /// Changes whenever the policy changes
+    pub id: String,
+}
+
+impl MtaStsRecord {
+    pub fn from_txt(record: &TxtRecord) -> Result<Self, TxtParseError> {
+        let text = text(record)?;
+        let tags = tag_list(&text)?;
+        check_version(&tags, "STSv1")?;
+        let id = find(&tags, "id").ok_or(TxtParseError::MissingTag("id"))?;
+        if id.is_empty() || id.len() > 32 || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(TxtParseError::Invalid(id.to_string()));
+        }
+        Ok(MtaStsRecord { id: id.to_string() })
+    }
+}
+
+/// How strictly an MTA-STS policy is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtaStsMode {
+    Enforce,
+    Testing,
+    None,
+}
+
+/// The policy file announced by [`MtaStsRecord`] (RFC 8461 section 3.2)
+///
+/// The file itself is served over HTTPS, only its parsing lives here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtaStsPolicy {
+    pub mode: MtaStsMode,
+    /// Allowed MX hosts, a leading `*.` matches exactly one label
+    pub mx: Vec<String>,
+    pub max_age: Duration,
+}
+
+impl MtaStsPolicy {
+    pub fn parse(text: &str) -> Result<Self, TxtParseError> {
+        let mut version = None;
+        let mut mode = None;
+        let mut mx = Vec::new();
+        let mut max_age = None;
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| TxtParseError::Invalid(line.to_string()))?;
+            let value = value.trim();
+            match key.trim() {
+                "version" => version = Some(value),
+                "mode" => {
+                    mode = Some(match value {
+                        "enforce" => MtaStsMode::Enforce,
+                        "testing" => MtaStsMode::Testing,
+                        "none" => MtaStsMode::None,
+                        _ => return Err(TxtParseError::Invalid(line.to_string())),
+                    })
+                }
+                "mx" => mx.push(value.to_string()),
+                "max_age" => {
+                    let seconds = value
+                        .parse()
+                        .map_err(|_| TxtParseError::Invalid(line.to_string()))?;
+                    max_age = Some(Duration::from_secs(seconds));
+                }
+                // unknown fields are ignored for extensibility
+                _ => {}
+            }
+        }
+        if version != Some("STSv1") {
+            return Err(TxtParseError::WrongVersion);
+        }
+        Ok(MtaStsPolicy {
+            mode: mode.ok_or(TxtParseError::MissingTag("mode"))?,
+            mx,
+            max_age: max_age.ok_or(TxtParseError::MissingTag("max_age"))?,
+        })
+    }
+
+    /// True if `host` is one of the allowed MX hosts
+    pub fn matches(&self, host: &DomainName) -> bool {
+        let host = host.as_str();
+        self.mx.iter().any(|pattern| {
+            let pattern = pattern.trim_end_matches('.');
+            match pattern.strip_prefix("*.") {
+                Some(parent) => host
+                    .split_once('.')
+                    .is_some_and(|(_, rest)| rest.eq_ignore_ascii_case(parent)),
+                None => host.eq_ignore_ascii_case(pattern),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txt(strings: &[&str]) -> TxtRecord {
+        TxtRecord {
+            strings: strings.iter().map(|s| s.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    #[test]
+    fn spf() {
+        let record = SpfRecord::from_txt(&txt(&[
+            "v=spf1 ip4:192.0.2.0/24 a/24 include:_spf.example.com ",
+            "~all redirect=_spf.example.net",
+        ]))
+        .unwrap();
+        assert_eq!(
+            record.terms,
+            [
+                SpfTerm::Mechanism {
+                    qualifier: SpfQualifier::Pass,
+                    name: "ip4".into(),
+                    argument: Some("192.0.2.0/24".into()),
+                },
+                SpfTerm::Mechanism {
+                    qualifier: SpfQualifier::Pass,
+                    name: "a".into(),
+                    argument: Some("/24".into()),
+                },
+                SpfTerm::Mechanism {
+                    qualifier: SpfQualifier::Pass,
+                    name: "include".into(),
+                    argument: Some("_spf.example.com".into()),
+                },
+                SpfTerm::Mechanism {
+                    qualifier: SpfQualifier::SoftFail,
+                    name: "all".into(),
+                    argument: None,
+                },
+                SpfTerm::Modifier {
+                    name: "redirect".into(),
+                    value: "_spf.example.net".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            SpfRecord::from_txt(&txt(&["v=spf10 -all"])),
+            Err(TxtParseError::WrongVersion)
+        );
+        assert_eq!(
+            SpfRecord::from_txt(&txt(&["v=spf1 -foo"])),
+            Err(TxtParseError::Invalid("-foo".into()))
+        );
+    }
+
+    #[test]
+    fn dkim_key_split_over_strings() {
+        let key = DkimKey::from_txt(&txt(&[
+            "v=DKIM1; k=rsa; t=y; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQ",
+            "DwIRP/UC3SBsEmGqZ9ZJW3/DkMoGeLnQg1fWn7/zYt",
+        ]))
+        .unwrap();
+        assert_eq!(key.key_type, "rsa");
+        assert_eq!(
+            key.public_key,
+            "MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDwIRP/UC3SBsEmGqZ9ZJW3/DkMoGeLnQg1fWn7/zYt"
+        );
+        assert_eq!(key.flags, ["y"]);
+        assert!(DkimKey::from_txt(&txt(&["v=DKIM1; p="]))
+            .unwrap()
+            .is_revoked());
+        assert_eq!(
+            DkimKey::from_txt(&txt(&["v=DKIM1; k=rsa"])),
+            Err(TxtParseError::MissingTag("p"))
+        );
+    }
+
+    #[test]
+    fn dmarc() {
+        let record = DmarcRecord::from_txt(&txt(&[
+            "v=DMARC1; p=quarantine; sp=reject; pct=50; adkim=s; rua=mailto:a@example.com,mailto:b@example.com",
+        ]))
+        .unwrap();
+        assert_eq!(record.policy, DmarcPolicy::Quarantine);
+        assert_eq!(record.subdomain_policy, Some(DmarcPolicy::Reject));
+        assert_eq!(record.percentage, 50);
+        assert!(record.strict_dkim);
+        assert!(!record.strict_spf);
+        assert_eq!(record.aggregate_reports.len(), 2);
+        assert_eq!(
+            DmarcRecord::from_txt(&txt(&["p=reject; v=DMARC1"])),
+            Err(TxtParseError::WrongVersion)
+        );
+    }
+
+    #[test]
+    fn mta_sts() {
+        let record = MtaStsRecord::from_txt(&txt(&["v=STSv1; id=20160831085700Z;"])).unwrap();
+        assert_eq!(record.id, "20160831085700Z");
+
+        let policy = MtaStsPolicy::parse(
+            "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.example.net\r\nmax_age: 604800\r\n",
+        )
+        .unwrap();
+        assert_eq!(policy.mode, MtaStsMode::Enforce);
+        assert_eq!(policy.max_age, Duration::from_secs(604800));
+        assert!(policy.matches(&"mail.example.com".parse().unwrap()));
+        assert!(policy.matches(&"mx1.example.net".parse().unwrap()));
+        assert!(!policy.matches(&"a.mx1.example.net".parse().unwrap()));
+        assert!(!policy.matches(&"example.net".parse().unwrap()));
+    }
+}
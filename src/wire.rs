@@ -0,0 +1,1276 @@
+//! DNS messages in the wire format of RFC 1035 section 4
+//!
+//! [`Message`] covers the header flags, all four sections and the EDNS(0)
+//! OPT pseudo-record of RFC 6891. Encoding compresses names where RFC 3597
+//! section 4 allows it. Decoding follows compression pointers only
+//! backwards, so crafted messages can not send the parser into a loop, and
+//! reports every inconsistency as [`ResolveError::Malformed`].
+//!
+//! The conversions to and from [`Record`] let backends and mock servers
+//! share the mapping between single resource records and typed answers.
+
+use crate::name::{escape_label, label_octets};
+use crate::{
+    CaaRecord, DnskeyRecord, DomainName, DsRecord, MxRecord, NaptrRecord, QueryType, Record,
+    RecordClass, RecordData, RecordSource, ResolveError, ResolveResult, ResponseCode, SoaRecord,
+    SrvRecord, SvcbRecord, TlsaRecord, TxtRecord, UnknownRecord,
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+/// Type code of the OPT pseudo-record
+pub const OPT: u16 = 41;
+
+/// CNAME records followed by [`Message::into_record`] before giving up
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Compression pointers can only address the first 16 KiB
+const MAX_POINTER: usize = 0x3fff;
+
+/// A complete DNS message, query or response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    /// QR, set in responses
+    pub response: bool,
+    pub opcode: u8,
+    /// AA, the answer comes from an authoritative server
+    pub authoritative: bool,
+    /// TC, the response did not fit and has to be fetched over TCP
+    pub truncated: bool,
+    /// RD, ask the server to resolve recursively
+    pub recursion_desired: bool,
+    /// RA, the server supports recursion
+    pub recursion_available: bool,
+    /// AD, the server validated the answer with DNSSEC (RFC 4035)
+    pub authentic_data: bool,
+    /// CD, the client does its own DNSSEC validation
+    pub checking_disabled: bool,
+    /// The full response code, including the upper bits carried in EDNS
+    pub rcode: ResponseCode,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    /// The additional section without the OPT record, see `edns`
+    pub additional: Vec<ResourceRecord>,
+    pub edns: Option<Edns>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: DomainName,
+    pub qtype: QueryType,
+    pub class: RecordClass,
+}
+
+/// A single record of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceRecord {
+    pub name: DomainName,
+    pub class: RecordClass,
+    pub ttl: u32,
+    pub data: Rdata,
+}
+
+/// The EDNS(0) parameters of the OPT pseudo-record (RFC 6891)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    /// Largest UDP response the sender can reassemble
+    pub udp_payload_size: u16,
+    pub version: u8,
+    /// DO, the sender wants DNSSEC records
+    pub dnssec_ok: bool,
+    /// The options as code and raw value
+    pub options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Default for Edns {
+    /// The payload size recommended by DNS flag day 2020
+    fn default() -> Self {
+        Edns {
+            udp_payload_size: 1232,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+/// The data of a single record
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rdata {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(DomainName),
+    CNAME(DomainName),
+    PTR(DomainName),
+    MX(MxRecord),
+    TXT(TxtRecord),
+    SOA(SoaRecord),
+    SRV(SrvRecord),
+    CAA(CaaRecord),
+    TLSA(TlsaRecord),
+    SVCB(SvcbRecord),
+    HTTPS(SvcbRecord),
+    NAPTR(NaptrRecord),
+    DS(DsRecord),
+    DNSKEY(DnskeyRecord),
+    Unknown(UnknownRecord),
+}
+
+impl Rdata {
+    pub fn rtype(&self) -> QueryType {
+        match self {
+            Rdata::A(_) => QueryType::A,
+            Rdata::AAAA(_) => QueryType::AAAA,
+            Rdata::NS(_) => QueryType::NS,
+            Rdata::CNAME(_) => QueryType::CNAME,
+            Rdata::PTR(_) => QueryType::PTR,
+            Rdata::MX(_) => QueryType::MX,
+            Rdata::TXT(_) => QueryType::TXT,
+            Rdata::SOA(_) => QueryType::SOA,
+            Rdata::SRV(_) => QueryType::SRV,
+            Rdata::CAA(_) => QueryType::CAA,
+            Rdata::TLSA(_) => QueryType::TLSA,
+            Rdata::SVCB(_) => QueryType::SVCB,
+            Rdata::HTTPS(_) => QueryType::HTTPS,
+            Rdata::NAPTR(_) => QueryType::NAPTR,
+            Rdata::DS(_) => QueryType::DS,
+            Rdata::DNSKEY(_) => QueryType::DNSKEY,
+            Rdata::Unknown(unknown) => QueryType::from(unknown.rtype),
+        }
+    }
+
+    /// Split typed data into the data of single records
+    pub fn from_record_data(data: &RecordData) -> Vec<Rdata> {
+        fn each<T: Clone>(items: &[T], wrap: fn(T) -> Rdata) -> Vec<Rdata> {
+            items.iter().cloned().map(wrap).collect()
+        }
+
+        match data {
+            RecordData::IpAddr(addresses) => addresses
+                .iter()
+                .map(|address| match address {
+                    IpAddr::V4(v4) => Rdata::A(*v4),
+                    IpAddr::V6(v6) => Rdata::AAAA(*v6),
+                })
+                .collect(),
+            RecordData::MX(mx) => each(mx, Rdata::MX),
+            RecordData::TXT(txt) => each(txt, Rdata::TXT),
+            RecordData::CNAME(target) => vec![Rdata::CNAME(target.clone())],
+            RecordData::NS(ns) => each(ns, Rdata::NS),
+            RecordData::PTR(ptr) => each(ptr, Rdata::PTR),
+            RecordData::SOA(soa) => vec![Rdata::SOA(soa.clone())],
+            RecordData::SRV(srv) => each(srv, Rdata::SRV),
+            RecordData::CAA(caa) => each(caa, Rdata::CAA),
+            RecordData::TLSA(tlsa) => each(tlsa, Rdata::TLSA),
+            RecordData::SVCB(svcb) => each(svcb, Rdata::SVCB),
+            RecordData::HTTPS(https) => each(https, Rdata::HTTPS),
+            RecordData::NAPTR(naptr) => each(naptr, Rdata::NAPTR),
+            RecordData::DS(ds) => each(ds, Rdata::DS),
+            RecordData::DNSKEY(dnskey) => each(dnskey, Rdata::DNSKEY),
+            RecordData::Unknown(unknown) => each(unknown, Rdata::Unknown),
+        }
+    }
+
+    /// Group the data of single records into typed data for `query`
+    ///
+    /// Records of other types are dropped, `None` if nothing is left. Opaque
+    /// RDATA of a type with typed data is only kept, as RFC 3597 records, if
+    /// there is no typed data for `query`.
+    pub fn into_record_data(query: QueryType, data: Vec<Rdata>) -> Option<RecordData> {
+        // `QueryType::Unknown(28)` is AAAA
+        let query = QueryType::from(u16::from(query));
+        let data: Vec<_> = data
+            .into_iter()
+            .filter(|data| data.rtype() == query)
+            .collect();
+        if data.is_empty() {
+            return None;
+        }
+
+        macro_rules! collect {
+            ($variant:ident) => {
+                data.into_iter()
+                    .filter_map(|data| match data {
+                        Rdata::$variant(value) => Some(value),
+                        _ => None,
+                    })
+                    .collect()
+            };
+        }
+
+        if data.iter().all(|data| matches!(data, Rdata::Unknown(_))) {
+            return Some(RecordData::Unknown(collect!(Unknown)));
+        }
+        Some(match query {
+            QueryType::A | QueryType::AAAA => RecordData::IpAddr(
+                data.into_iter()
+                    .filter_map(|data| match data {
+                        Rdata::A(v4) => Some(IpAddr::V4(v4)),
+                        Rdata::AAAA(v6) => Some(IpAddr::V6(v6)),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            QueryType::MX => RecordData::MX(collect!(MX)),
+            QueryType::TXT => RecordData::TXT(collect!(TXT)),
+            QueryType::NS => RecordData::NS(collect!(NS)),
+            QueryType::PTR => RecordData::PTR(collect!(PTR)),
+            QueryType::SRV => RecordData::SRV(collect!(SRV)),
+            QueryType::CAA => RecordData::CAA(collect!(CAA)),
+            QueryType::TLSA => RecordData::TLSA(collect!(TLSA)),
+            QueryType::SVCB => RecordData::SVCB(collect!(SVCB)),
+            QueryType::HTTPS => RecordData::HTTPS(collect!(HTTPS)),
+            QueryType::NAPTR => RecordData::NAPTR(collect!(NAPTR)),
+            QueryType::DS => RecordData::DS(collect!(DS)),
+            QueryType::DNSKEY => RecordData::DNSKEY(collect!(DNSKEY)),
+            QueryType::Unknown(_) => RecordData::Unknown(collect!(Unknown)),
+            // there is only one of these per name
+            QueryType::CNAME | QueryType::SOA => data.into_iter().find_map(|data| match data {
+                Rdata::CNAME(target) => Some(RecordData::CNAME(target)),
+                Rdata::SOA(soa) => Some(RecordData::SOA(soa)),
+                _ => None,
+            })?,
+        })
+    }
+}
+
+impl Message {
+    /// A recursive query for `name` with a single question
+    pub fn query(id: u16, name: &DomainName, qtype: QueryType) -> Self {
+        Message {
+            id,
+            response: false,
+            opcode: 0,
+            authoritative: false,
+            truncated: false,
+            recursion_desired: true,
+            recursion_available: false,
+            authentic_data: false,
+            checking_disabled: false,
+            rcode: ResponseCode::NoError,
+            questions: vec![Question {
+                name: name.to_fqdn(),
+                qtype,
+                class: RecordClass::IN,
+            }],
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+            edns: None,
+        }
+    }
+
+    /// An empty response to this query, the starting point of servers
+    pub fn reply(&self, rcode: ResponseCode) -> Self {
+        Message {
+            id: self.id,
+            response: true,
+            opcode: self.opcode,
+            authoritative: false,
+            truncated: false,
+            recursion_desired: self.recursion_desired,
+            recursion_available: true,
+            authentic_data: false,
+            checking_disabled: self.checking_disabled,
+            rcode,
+            questions: self.questions.clone(),
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+            edns: self.edns.as_ref().map(|_| Edns::default()),
+        }
+    }
+
+    /// The message in wire format
+    ///
+    /// Panics if a section holds more than 65535 records or RDATA, an EDNS
+    /// option or a SvcParam is longer than 65535 octets, the format has no
+    /// room for more.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer {
+            out: Vec::with_capacity(512),
+            names: HashMap::new(),
+        };
+        let rcode = u16::from(self.rcode);
+        let flags = u16::from(self.response) << 15
+            | u16::from(self.opcode & 0xf) << 11
+            | u16::from(self.authoritative) << 10
+            | u16::from(self.truncated) << 9
+            | u16::from(self.recursion_desired) << 8
+            | u16::from(self.recursion_available) << 7
+            | u16::from(self.authentic_data) << 5
+            | u16::from(self.checking_disabled) << 4
+            | (rcode & 0xf);
+        writer.u16(self.id);
+        writer.u16(flags);
+        for (count, section) in [
+            (self.questions.len(), "question"),
+            (self.answers.len(), "answer"),
+            (self.authority.len(), "authority"),
+            (
+                self.additional.len() + usize::from(self.edns.is_some()),
+                "additional",
+            ),
+        ] {
+            writer.length(count, section);
+        }
+
+        for question in &self.questions {
+            writer.name(&question.name, true);
+            writer.u16(question.qtype.into());
+            writer.u16(question.class.into());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            record.encode(&mut writer);
+        }
+        if let Some(edns) = &self.edns {
+            // the class carries the payload size, the TTL the upper rcode
+            // bits, the version and the flags
+            writer.out.push(0);
+            writer.u16(OPT);
+            writer.u16(edns.udp_payload_size);
+            writer.out.push((rcode >> 4) as u8);
+            writer.out.push(edns.version);
+            writer.u16(if edns.dnssec_ok { 0x8000 } else { 0 });
+            let length_at = writer.out.len();
+            writer.u16(0);
+            for (code, value) in &edns.options {
+                writer.u16(*code);
+                writer.length(value.len(), "EDNS option");
+                writer.out.extend_from_slice(value);
+            }
+            writer.patch_length(length_at);
+        }
+        writer.out
+    }
+
+    pub fn decode(data: &[u8]) -> ResolveResult<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        let (mut message, counts) = Self::decode_head(&mut reader)?;
+        for _ in 0..counts[1] {
+            message.answers.push(ResourceRecord::decode(&mut reader)?);
+        }
+        for _ in 0..counts[2] {
+            message.authority.push(ResourceRecord::decode(&mut reader)?);
+        }
+
+        let mut edns = None;
+        let mut rcode = u16::from(message.rcode);
+        for _ in 0..counts[3] {
+            let start = reader.pos;
+            let owner = reader.name()?;
+            if reader.u16()? != OPT {
+                reader.pos = start;
+                message
+                    .additional
+                    .push(ResourceRecord::decode(&mut reader)?);
+                continue;
+            }
+            if edns.is_some() || !owner.is_root() {
+                return Err(malformed("invalid OPT record"));
+            }
+            let udp_payload_size = reader.u16()?;
+            rcode |= u16::from(reader.u8()?) << 4;
+            let version = reader.u8()?;
+            let dnssec_ok = reader.u16()? & 0x8000 != 0;
+            let end = reader.end()?;
+            let mut options = Vec::new();
+            while reader.pos < end {
+                let code = reader.u16()?;
+                let len = usize::from(reader.u16()?);
+                options.push((code, reader.bytes(len)?.to_vec()));
+            }
+            reader.expect_end(end)?;
+            edns = Some(Edns {
+                udp_payload_size,
+                version,
+                dnssec_ok,
+                options,
+            });
+        }
+        message.rcode = ResponseCode::from(rcode);
+        message.edns = edns;
+        Ok(message)
+    }
+
+    /// Only the header and the question section, enough to tell which query
+    /// a response belongs to when the records do not decode
+    pub fn decode_question(data: &[u8]) -> ResolveResult<Self> {
+        let (message, _) = Self::decode_head(&mut Reader { data, pos: 0 })?;
+        Ok(message)
+    }
+
+    /// The header and the questions, with the counts of all sections
+    fn decode_head(reader: &mut Reader) -> ResolveResult<(Self, [u16; 4])> {
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+        let mut questions = Vec::new();
+        for _ in 0..counts[0] {
+            questions.push(Question {
+                name: reader.name()?,
+                qtype: QueryType::from(reader.u16()?),
+                class: RecordClass::from(reader.u16()?),
+            });
+        }
+
+        let message = Message {
+            id,
+            response: flags & 0x8000 != 0,
+            opcode: (flags >> 11 & 0xf) as u8,
+            authoritative: flags & 0x0400 != 0,
+            truncated: flags & 0x0200 != 0,
+            recursion_desired: flags & 0x0100 != 0,
+            recursion_available: flags & 0x0080 != 0,
+            authentic_data: flags & 0x0020 != 0,
+            checking_disabled: flags & 0x0010 != 0,
+            rcode: ResponseCode::from(flags & 0xf),
+            questions,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+            edns: None,
+        };
+        Ok((message, counts))
+    }
+
+    /// How long a negative answer may be cached, the smaller of the TTL and
+    /// the MINIMUM of the SOA record in the authority section (RFC 2308)
+    fn negative_ttl(&self) -> Option<Duration> {
+        self.authority.iter().find_map(|record| match &record.data {
+            Rdata::SOA(soa) => Some(Duration::from_secs(record.ttl.into()).min(soa.minimum)),
+            _ => None,
+        })
+    }
+
+    /// The answer to `query` for `name` as a typed record
+    ///
+    /// CNAME records in the answer section are followed, the response code
+    /// is mapped to the matching [`ResolveError`].
+    pub fn into_record(self, name: &DomainName, query: QueryType) -> ResolveResult<Record> {
+        let negative_ttl = self.negative_ttl();
+        match self.rcode {
+            ResponseCode::NoError => {}
+            ResponseCode::NXDomain => {
+                return Err(ResolveError::NxDomain {
+                    name: name.clone(),
+                    query,
+                    ttl: negative_ttl,
+                })
+            }
+            rcode => {
+                return Err(ResolveError::Server {
+                    rcode,
+                    name: name.clone(),
+                    query,
+                })
+            }
+        }
+
+        let answers: Vec<_> = self
+            .answers
+            .into_iter()
+            .filter(|record| record.class == RecordClass::IN)
+            .collect();
+
+        let mut owner = name.to_fqdn();
+        let mut ttl = u32::MAX;
+        if query != QueryType::CNAME {
+            for _ in 0..MAX_CNAME_CHAIN {
+                let alias = answers.iter().find_map(|record| match &record.data {
+                    Rdata::CNAME(target) if record.name == owner => Some((record.ttl, target)),
+                    _ => None,
+                });
+                match alias {
+                    Some((alias_ttl, target)) => {
+                        ttl = ttl.min(alias_ttl);
+                        owner = target.clone();
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let mut matching = Vec::new();
+        for record in answers {
+            if record.name == owner && record.data.rtype() == query {
+                ttl = ttl.min(record.ttl);
+                matching.push(record.data);
+            }
+        }
+        let data =
+            Rdata::into_record_data(query, matching).ok_or_else(|| ResolveError::NoData {
+                name: name.clone(),
+                query,
+                ttl: negative_ttl,
+            })?;
+
+        Ok(Record {
+            name: name.clone(),
+            canonical_name: owner,
+            class: RecordClass::IN,
+            ttl: Duration::from_secs(ttl.into()),
+            source: if self.authoritative {
+                RecordSource::Authoritative
+            } else {
+                RecordSource::Recursive
+            },
+            data,
+        })
+    }
+}
+
+impl ResourceRecord {
+    /// The records of an answer section that carry `record`
+    ///
+    /// An alias is written as CNAME from the queried to the canonical name.
+    pub fn from_record(record: &Record) -> Vec<ResourceRecord> {
+        let ttl = record.ttl.as_secs().min(u32::MAX.into()) as u32;
+        let owner = record.canonical_name.to_fqdn();
+        let mut records = Vec::new();
+        if record.name.to_fqdn() != owner {
+            records.push(ResourceRecord {
+                name: record.name.to_fqdn(),
+                class: record.class,
+                ttl,
+                data: Rdata::CNAME(owner.clone()),
+            });
+        }
+        records.extend(
+            Rdata::from_record_data(&record.data)
+                .into_iter()
+                .map(|data| ResourceRecord {
+                    name: owner.clone(),
+                    class: record.class,
+                    ttl,
+                    data,
+                }),
+        );
+        records
+    }
+
+    fn encode(&self, writer: &mut Writer) {
+        writer.name(&self.name, true);
+        writer.u16(self.data.rtype().into());
+        writer.u16(self.class.into());
+        writer.out.extend_from_slice(&self.ttl.to_be_bytes());
+        let length_at = writer.out.len();
+        writer.u16(0);
+
+        match &self.data {
+            Rdata::A(v4) => writer.out.extend_from_slice(&v4.octets()),
+            Rdata::AAAA(v6) => writer.out.extend_from_slice(&v6.octets()),
+            Rdata::NS(name) | Rdata::CNAME(name) | Rdata::PTR(name) => writer.name(name, true),
+            Rdata::MX(mx) => {
+                writer.u16(mx.preference);
+                writer.name(&mx.exchange, true);
+            }
+            Rdata::TXT(txt) => {
+                for string in &txt.strings {
+                    writer.character_string(string);
+                }
+            }
+            Rdata::SOA(soa) => {
+                writer.name(&soa.mname, true);
+                writer.name(&soa.rname, true);
+                writer.out.extend_from_slice(&soa.serial.to_be_bytes());
+                for time in [soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    let secs = time.as_secs().min(u32::MAX.into()) as u32;
+                    writer.out.extend_from_slice(&secs.to_be_bytes());
+                }
+            }
+            Rdata::SRV(srv) => {
+                writer.u16(srv.priority);
+                writer.u16(srv.weight);
+                writer.u16(srv.port);
+                writer.name(&srv.target, false);
+            }
+            Rdata::CAA(caa) => {
+                writer.out.push(caa.flags);
+                writer.character_string(caa.tag.as_bytes());
+                writer.out.extend_from_slice(&caa.value);
+            }
+            Rdata::TLSA(tlsa) => {
+                writer
+                    .out
+                    .extend_from_slice(&[tlsa.cert_usage, tlsa.selector, tlsa.matching_type]);
+                writer.out.extend_from_slice(&tlsa.data);
+            }
+            Rdata::SVCB(svcb) | Rdata::HTTPS(svcb) => {
+                writer.u16(svcb.priority);
+                writer.name(&svcb.target, false);
+                for (key, value) in &svcb.params {
+                    writer.u16(*key);
+                    writer.length(value.len(), "SvcParam");
+                    writer.out.extend_from_slice(value);
+                }
+            }
+            Rdata::NAPTR(naptr) => {
+                writer.u16(naptr.order);
+                writer.u16(naptr.preference);
+                writer.character_string(naptr.flags.as_bytes());
+                writer.character_string(naptr.services.as_bytes());
+                writer.character_string(naptr.regexp.as_bytes());
+                writer.name(&naptr.replacement, false);
+            }
+            Rdata::DS(ds) => {
+                writer.u16(ds.key_tag);
+                writer
+                    .out
+                    .extend_from_slice(&[ds.algorithm, ds.digest_type]);
+                writer.out.extend_from_slice(&ds.digest);
+            }
+            Rdata::DNSKEY(dnskey) => {
+                writer.u16(dnskey.flags);
+                writer
+                    .out
+                    .extend_from_slice(&[dnskey.protocol, dnskey.algorithm]);
+                writer.out.extend_from_slice(&dnskey.public_key);
+            }
+            Rdata::Unknown(unknown) => writer.out.extend_from_slice(&unknown.rdata),
+        }
+
+        writer.patch_length(length_at);
+    }
+
+    fn decode(reader: &mut Reader) -> ResolveResult<Self> {
+        let name = reader.name()?;
+        let rtype = reader.u16()?;
+        let class = RecordClass::from(reader.u16()?);
+        let ttl = reader.u32()?;
+        let end = reader.end()?;
+
+        let data = match QueryType::from(rtype) {
+            QueryType::A => Rdata::A(Ipv4Addr::from(reader.array::<4>()?)),
+            QueryType::AAAA => Rdata::AAAA(Ipv6Addr::from(reader.array::<16>()?)),
+            QueryType::NS => Rdata::NS(reader.name()?),
+            QueryType::CNAME => Rdata::CNAME(reader.name()?),
+            QueryType::PTR => Rdata::PTR(reader.name()?),
+            QueryType::MX => Rdata::MX(MxRecord {
+                preference: reader.u16()?,
+                exchange: reader.name()?,
+            }),
+            QueryType::TXT => {
+                let mut strings = Vec::new();
+                while reader.pos < end {
+                    strings.push(reader.character_string()?.to_vec());
+                }
+                Rdata::TXT(TxtRecord { strings })
+            }
+            QueryType::SOA => Rdata::SOA(SoaRecord {
+                mname: reader.name()?,
+                rname: reader.name()?,
+                serial: reader.u32()?,
+                refresh: Duration::from_secs(reader.u32()?.into()),
+                retry: Duration::from_secs(reader.u32()?.into()),
+                expire: Duration::from_secs(reader.u32()?.into()),
+                minimum: Duration::from_secs(reader.u32()?.into()),
+            }),
+            QueryType::SRV => Rdata::SRV(SrvRecord {
+                priority: reader.u16()?,
+                weight: reader.u16()?,
+                port: reader.u16()?,
+                target: reader.name()?,
+            }),
+            QueryType::CAA => Rdata::CAA(CaaRecord {
+                flags: reader.u8()?,
+                tag: reader.text()?,
+                value: reader.rest(end)?.to_vec(),
+            }),
+            QueryType::TLSA => Rdata::TLSA(TlsaRecord {
+                cert_usage: reader.u8()?,
+                selector: reader.u8()?,
+                matching_type: reader.u8()?,
+                data: reader.rest(end)?.to_vec(),
+            }),
+            qtype @ (QueryType::SVCB | QueryType::HTTPS) => {
+                let priority = reader.u16()?;
+                let target = reader.name()?;
+                let mut params = Vec::new();
+                while reader.pos < end {
+                    let key = reader.u16()?;
+                    let len = usize::from(reader.u16()?);
+                    params.push((key, reader.bytes(len)?.to_vec()));
+                }
+                let svcb = SvcbRecord {
+                    priority,
+                    target,
+                    params,
+                };
+                if qtype == QueryType::SVCB {
+                    Rdata::SVCB(svcb)
+                } else {
+                    Rdata::HTTPS(svcb)
+                }
+            }
+            QueryType::NAPTR => Rdata::NAPTR(NaptrRecord {
+                order: reader.u16()?,
+                preference: reader.u16()?,
+                flags: reader.text()?,
+                services: reader.text()?,
+                regexp: reader.text()?,
+                replacement: reader.name()?,
+            }),
+            QueryType::DS => Rdata::DS(DsRecord {
+                key_tag: reader.u16()?,
+                algorithm: reader.u8()?,
+                digest_type: reader.u8()?,
+                digest: reader.rest(end)?.to_vec(),
+            }),
+            QueryType::DNSKEY => Rdata::DNSKEY(DnskeyRecord {
+                flags: reader.u16()?,
+                protocol: reader.u8()?,
+                algorithm: reader.u8()?,
+                public_key: reader.rest(end)?.to_vec(),
+            }),
+            QueryType::Unknown(_) => Rdata::Unknown(UnknownRecord {
+                rtype,
+                rdata: reader.rest(end)?.to_vec(),
+            }),
+        };
+        reader.expect_end(end)?;
+
+        Ok(ResourceRecord {
+            name,
+            class,
+            ttl,
+            data,
+        })
+    }
+}
+
+fn malformed(reason: &str) -> ResolveError {
+    ResolveError::Malformed(reason.to_string())
+}
+
+struct Writer {
+    out: Vec<u8>,
+    /// Offsets of the names written so far, keyed by their lowercase form
+    names: HashMap<String, u16>,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Write a count or length, which have 16 bits at most
+    fn length(&mut self, length: usize, what: &str) {
+        self.u16(sixteen_bits(length, what));
+    }
+
+    /// Fill in the RDLENGTH reserved at `length_at`
+    fn patch_length(&mut self, length_at: usize) {
+        let length = sixteen_bits(self.out.len() - length_at - 2, "RDATA");
+        self.out[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+    }
+
+    /// Write `name`, pointing to an earlier copy of a suffix if `compress`
+    fn name(&mut self, name: &DomainName, compress: bool) {
+        let labels: Vec<&str> = name.labels().collect();
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+            if compress {
+                if let Some(offset) = self.names.get(&suffix) {
+                    self.u16(0xc000 | offset);
+                    return;
+                }
+            }
+            if self.out.len() <= MAX_POINTER {
+                self.names.entry(suffix).or_insert(self.out.len() as u16);
+            }
+            let label = label_octets(labels[i]);
+            self.out.push(label.len() as u8);
+            self.out.extend_from_slice(&label);
+        }
+        self.out.push(0);
+    }
+
+    /// Longer strings are cut at 255 bytes
+    fn character_string(&mut self, string: &[u8]) {
+        let string = &string[..string.len().min(255)];
+        self.out.push(string.len() as u8);
+        self.out.extend_from_slice(string);
+    }
+}
+
+fn sixteen_bits(length: usize, what: &str) -> u16 {
+    u16::try_from(length).unwrap_or_else(|_| panic!("{} exceeds 65535", what))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> ResolveResult<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| malformed("message too short"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> ResolveResult<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> ResolveResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> ResolveResult<u16> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> ResolveResult<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    /// Read RDLENGTH and return where the data ends
+    fn end(&mut self) -> ResolveResult<usize> {
+        let end = usize::from(self.u16()?) + self.pos;
+        if end > self.data.len() {
+            return Err(malformed("record data exceeds the message"));
+        }
+        Ok(end)
+    }
+
+    fn expect_end(&self, end: usize) -> ResolveResult<()> {
+        if self.pos == end {
+            Ok(())
+        } else {
+            Err(malformed("record data length does not match"))
+        }
+    }
+
+    /// Everything up to `end`
+    fn rest(&mut self, end: usize) -> ResolveResult<&'a [u8]> {
+        let len = end
+            .checked_sub(self.pos)
+            .ok_or_else(|| malformed("record data length does not match"))?;
+        self.bytes(len)
+    }
+
+    fn character_string(&mut self) -> ResolveResult<&'a [u8]> {
+        let len = usize::from(self.u8()?);
+        self.bytes(len)
+    }
+
+    fn text(&mut self) -> ResolveResult<String> {
+        String::from_utf8(self.character_string()?.to_vec())
+            .map_err(|_| malformed("character-string is not UTF-8"))
+    }
+
+    /// Read a possibly compressed name
+    ///
+    /// Every pointer has to point before the label it was found in. That
+    /// alone does not rule out loops, as the labels after the target lead
+    /// back to the pointer. What ends them is the limit of 255 octets per
+    /// name: every pass through a loop adds its labels to the name.
+    /// Names are taken as they are, without the hostname rules of
+    /// [`DomainName::new`]
+    fn name(&mut self) -> ResolveResult<DomainName> {
+        let mut name = String::new();
+        let mut wire_len = 1;
+        let mut pos = self.pos;
+        let mut resume = None;
+        loop {
+            let len = *self
+                .data
+                .get(pos)
+                .ok_or_else(|| malformed("name exceeds the message"))?;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    pos += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self
+                        .data
+                        .get(pos + 1..pos + 1 + usize::from(len))
+                        .ok_or_else(|| malformed("label exceeds the message"))?;
+                    wire_len += 1 + label.len();
+                    if wire_len > 255 {
+                        return Err(malformed("name too long"));
+                    }
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&escape_label(label));
+                    pos += 1 + usize::from(len);
+                }
+                0xc0 => {
+                    let low = *self
+                        .data
+                        .get(pos + 1)
+                        .ok_or_else(|| malformed("pointer exceeds the message"))?;
+                    let target = usize::from(u16::from_be_bytes([len & 0x3f, low]));
+                    if target >= pos {
+                        return Err(malformed("compression pointer does not point backwards"));
+                    }
+                    resume.get_or_insert(pos + 2);
+                    pos = target;
+                }
+                _ => return Err(malformed("unsupported label type")),
+            }
+        }
+        self.pos = resume.unwrap_or(pos);
+        Ok(DomainName::from_wire(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::random_below;
+
+    fn name(name: &str) -> DomainName {
+        name.parse().unwrap()
+    }
+
+    fn rr(owner: &str, data: Rdata) -> ResourceRecord {
+        ResourceRecord {
+            name: name(owner),
+            class: RecordClass::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    fn sample() -> Message {
+        let query = Message::query(0x1234, &name("example.com"), QueryType::MX);
+        let mut message = query.reply(ResponseCode::NoError);
+        message.authoritative = true;
+        message.authentic_data = true;
+        message.answers = vec![
+            rr(
+                "example.com.",
+                Rdata::MX(MxRecord {
+                    preference: 10,
+                    exchange: name("mx.example.com."),
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::TXT(TxtRecord {
+                    strings: vec![b"v=spf1".to_vec(), vec![0xff, 0x00]],
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::CAA(CaaRecord {
+                    flags: 128,
+                    tag: "issue".into(),
+                    value: b"ca.example".to_vec(),
+                }),
+            ),
+            rr(
+                "_443._tcp.example.com.",
+                Rdata::TLSA(TlsaRecord {
+                    cert_usage: 3,
+                    selector: 1,
+                    matching_type: 1,
+                    data: vec![0xab; 32],
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::HTTPS(SvcbRecord {
+                    priority: 1,
+                    target: DomainName::root(),
+                    params: vec![(SvcbRecord::KEY_ALPN, b"\x02h2".to_vec())],
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::NAPTR(NaptrRecord {
+                    order: 100,
+                    preference: 10,
+                    flags: "S".into(),
+                    services: "SIP+D2U".into(),
+                    regexp: String::new(),
+                    replacement: name("_sip._udp.example.com."),
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::DS(DsRecord {
+                    key_tag: 12345,
+                    algorithm: 13,
+                    digest_type: 2,
+                    digest: vec![1; 32],
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::DNSKEY(DnskeyRecord {
+                    flags: 257,
+                    protocol: 3,
+                    algorithm: 13,
+                    public_key: vec![2; 64],
+                }),
+            ),
+            rr(
+                "example.com.",
+                Rdata::Unknown(UnknownRecord {
+                    rtype: 65280,
+                    rdata: vec![1, 2, 3],
+                }),
+            ),
+        ];
+        message.authority = vec![rr(
+            "example.com.",
+            Rdata::SOA(SoaRecord {
+                mname: name("ns.example.com."),
+                rname: name("hostmaster.example.com."),
+                serial: 2024010101,
+                refresh: Duration::from_secs(7200),
+                retry: Duration::from_secs(3600),
+                expire: Duration::from_secs(1209600),
+                minimum: Duration::from_secs(300),
+            }),
+        )];
+        message.additional = vec![rr(
+            "mx.example.com.",
+            Rdata::AAAA("2001:db8::1".parse().unwrap()),
+        )];
+        message.edns = Some(Edns {
+            dnssec_ok: true,
+            options: vec![(10, vec![0; 8])],
+            ..Edns::default()
+        });
+        message
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = sample();
+        assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+    }
+
+    #[test]
+    fn extended_rcode() {
+        let mut message = sample();
+        // BADVERS, only representable with EDNS
+        message.rcode = ResponseCode::Other(16);
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded.rcode, ResponseCode::Other(16));
+    }
+
+    #[test]
+    fn compresses_names() {
+        let encoded = sample().encode();
+        let occurrences = encoded
+            .windows(8)
+            .filter(|window| window == b"\x07example")
+            .count();
+        // the NAPTR replacement must not be compressed
+        assert_eq!(occurrences, 2);
+    }
+
+    #[test]
+    fn compressed_names() {
+        let mut data = Message::query(1, &name("example.com"), QueryType::CNAME).encode();
+        data[7] = 1; // one answer
+                     // www.example.com. as "www" + pointer to the question name at 12
+        data.extend_from_slice(&[3, b'w', b'w', b'w', 0xc0, 12]);
+        data.extend_from_slice(&[0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+        let message = Message::decode(&data).unwrap();
+        assert_eq!(message.answers[0].name, name("www.example.com."));
+        assert_eq!(message.answers[0].data, Rdata::CNAME(name("example.com.")));
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        let mut data = Message::query(1, &name("example.com"), QueryType::A).encode();
+        data[7] = 1;
+        let start = data.len();
+        // a pointer to itself
+        data.extend_from_slice(&[0xc0, start as u8]);
+        data.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        assert!(matches!(
+            Message::decode(&data),
+            Err(ResolveError::Malformed(_))
+        ));
+
+        // a label followed by a pointer back to the label
+        data.truncate(start);
+        data.extend_from_slice(&[1, b'a', 0xc0, start as u8]);
+        data.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        assert!(matches!(
+            Message::decode(&data),
+            Err(ResolveError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "EDNS option exceeds 65535")]
+    fn refuses_oversized_values() {
+        let mut message = Message::query(1, &name("example.com"), QueryType::A);
+        message.edns = Some(Edns {
+            options: vec![(10, vec![0; 65536])],
+            ..Edns::default()
+        });
+        message.encode();
+    }
+
+    #[test]
+    fn survives_garbage() {
+        let encoded = sample().encode();
+        for len in 0..encoded.len() {
+            assert!(Message::decode(&encoded[..len]).is_err());
+        }
+        for _ in 0..2000 {
+            let mut mutated = encoded.clone();
+            for _ in 0..4 {
+                let i = random_below(mutated.len() as u64) as usize;
+                mutated[i] = random_below(256) as u8;
+            }
+            let _ = Message::decode(&mutated);
+        }
+    }
+
+    #[test]
+    fn names_outside_hostname_rules() {
+        // a wildcard, an RFC 2317 delegation and a label with a dot and a space
+        let mut bytes = Message::query(1, &name("example.com"), QueryType::PTR).encode();
+        bytes[5] = 4;
+        for labels in [
+            &[&b"*"[..], b"example", b"com"][..],
+            &[b"0/25", b"2", b"0", b"192", b"in-addr", b"arpa"],
+            &[b"a.b c", b"example"],
+        ] {
+            for label in labels {
+                bytes.push(label.len() as u8);
+                bytes.extend_from_slice(label);
+            }
+            bytes.extend_from_slice(&[0, 0, 12, 0, 1]);
+        }
+
+        let message = Message::decode(&bytes).unwrap();
+        let names: Vec<_> = message
+            .questions
+            .iter()
+            .map(|question| question.name.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "example.com.",
+                "*.example.com.",
+                "0/25.2.0.192.in-addr.arpa.",
+                "a\\.b\\032c.example."
+            ]
+        );
+        let labels: Vec<_> = message.questions[3].name.labels().collect();
+        assert_eq!(labels, ["a\\.b\\032c", "example"]);
+        assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+    }
+
+    #[test]
+    fn record_conversion() {
+        let mut record = Record::new(
+            name("www.example.com"),
+            Duration::from_secs(60),
+            RecordData::IpAddr(vec![
+                "192.0.2.1".parse().unwrap(),
+                "192.0.2.2".parse().unwrap(),
+            ]),
+        );
+        record.canonical_name = name("host.example.com.");
+
+        let mut response =
+            Message::query(7, &record.name, QueryType::A).reply(ResponseCode::NoError);
+        response.answers = ResourceRecord::from_record(&record);
+        assert_eq!(response.answers.len(), 3);
+
+        let decoded = Message::decode(&response.encode()).unwrap();
+        let converted = decoded.into_record(&record.name, QueryType::A).unwrap();
+        assert_eq!(converted.canonical_name, record.canonical_name);
+        assert_eq!(converted.ttl, record.ttl);
+        assert_eq!(converted.data, record.data);
+    }
+
+    #[test]
+    fn record_data_by_type_code() {
+        let v6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            Rdata::into_record_data(QueryType::Unknown(28), vec![Rdata::AAAA(v6)]),
+            Some(RecordData::IpAddr(vec![v6.into()]))
+        );
+
+        let opaque = UnknownRecord {
+            rtype: 5,
+            rdata: vec![0],
+        };
+        assert_eq!(
+            Rdata::into_record_data(QueryType::CNAME, vec![Rdata::Unknown(opaque.clone())]),
+            Some(RecordData::Unknown(vec![opaque.clone()]))
+        );
+        assert_eq!(
+            Rdata::into_record_data(
+                QueryType::CNAME,
+                vec![
+                    Rdata::Unknown(opaque),
+                    Rdata::CNAME(name("host.example.com."))
+                ]
+            ),
+            Some(RecordData::CNAME(name("host.example.com.")))
+        );
+    }
+
+    #[test]
+    fn negative_ttl_from_soa() {
+        let soa = |ttl| ResourceRecord {
+            ttl,
+            ..rr(
+                "example.com.",
+                Rdata::SOA(SoaRecord {
+                    mname: name("ns.example.com."),
+                    rname: name("hostmaster.example.com."),
+                    serial: 1,
+                    refresh: Duration::from_secs(7200),
+                    retry: Duration::from_secs(3600),
+                    expire: Duration::from_secs(1209600),
+                    minimum: Duration::from_secs(300),
+                }),
+            )
+        };
+        let query = name("missing.example.com");
+
+        let mut response = Message::query(7, &query, QueryType::A).reply(ResponseCode::NoError);
+        response.authority = vec![soa(3600)];
+        match response.into_record(&query, QueryType::A) {
+            Err(ResolveError::NoData { ttl, .. }) => {
+                assert_eq!(ttl, Some(Duration::from_secs(300)))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut response = Message::query(7, &query, QueryType::A).reply(ResponseCode::NXDomain);
+        response.authority = vec![soa(60)];
+        match response.into_record(&query, QueryType::A) {
+            Err(ResolveError::NxDomain { ttl, .. }) => {
+                assert_eq!(ttl, Some(Duration::from_secs(60)))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let response = Message::query(7, &query, QueryType::A).reply(ResponseCode::NXDomain);
+        let error = response.into_record(&query, QueryType::A).unwrap_err();
+        assert_eq!(error.negative_ttl(), None);
+    }
+}